@@ -0,0 +1,86 @@
+//! Compares the paged copy-on-write memory with the flat array it replaced.
+//! Run with `cargo bench -p backend`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use backend::{Memory, SynacorVM};
+
+const SNAPSHOTS: usize = 10_000;
+/// Words written between snapshots, roughly what a game turn touches.
+const WRITES_PER_SNAPSHOT: usize = 16;
+const READ_PASSES: usize = 200;
+const VM_STEPS: u64 = 5_000_000;
+
+fn main() {
+    println!("{:<32} {:>12} {:>12}", "", "array", "paged");
+
+    compare("clone", || {
+        let array = Box::new([0u16; 0x8000]);
+        let mut kept = Vec::with_capacity(SNAPSHOTS);
+        time(|| for _ in 0..SNAPSHOTS { kept.push(black_box(array.clone())); })
+    }, || {
+        let memory = Memory::new();
+        let mut kept = Vec::with_capacity(SNAPSHOTS);
+        time(|| for _ in 0..SNAPSHOTS { kept.push(black_box(memory.clone())); })
+    });
+
+    compare("clone, then write", || {
+        let mut array = Box::new([0u16; 0x8000]);
+        let mut kept = Vec::with_capacity(SNAPSHOTS);
+        time(|| for i in 0..SNAPSHOTS {
+            kept.push(array.clone());
+            for j in 0..WRITES_PER_SNAPSHOT { array[scatter(i, j)] = i as u16; }
+        })
+    }, || {
+        let mut memory = Memory::new();
+        let mut kept = Vec::with_capacity(SNAPSHOTS);
+        time(|| for i in 0..SNAPSHOTS {
+            kept.push(memory.clone());
+            for j in 0..WRITES_PER_SNAPSHOT { memory.set(scatter(i, j), i as u16); }
+        })
+    });
+
+    compare("sequential reads", || {
+        let array = Box::new([1u16; 0x8000]);
+        time(|| for _ in 0..READ_PASSES {
+            black_box((0..0x8000).fold(0u16, |sum, addr| sum.wrapping_add(array[addr])));
+        })
+    }, || {
+        let memory = Memory::new();
+        time(|| for _ in 0..READ_PASSES {
+            black_box((0..0x8000).fold(0u16, |sum, addr| sum.wrapping_add(memory[addr])));
+        })
+    });
+
+    let mut vm = SynacorVM::new();
+    vm.load_binary(&COUNTER);
+    let vm_time = time(|| for _ in 0..VM_STEPS { black_box(vm.step().unwrap()); });
+    println!("{:<32} {:>12} {:>12}", "vm steps/s", "-", format!("{:.1}M", VM_STEPS as f64 / vm_time.as_secs_f64() / 1e6));
+
+    let mut memory = Memory::new();
+    let kept = (0..SNAPSHOTS).map(|i| {
+        let snapshot = memory.clone();
+        for j in 0..WRITES_PER_SNAPSHOT { memory.set(scatter(i, j), i as u16); }
+        snapshot
+    }).collect::<Vec<_>>();
+    let pages = kept.windows(2).map(|w| w[0].unshared_pages(&w[1])).sum::<usize>();
+    println!("{:<32} {:>12} {:>12}", "KB held by snapshots", SNAPSHOTS * 64, pages / 2 + 64);
+}
+
+/// Loops forever, incrementing a counter in memory: `rmem`, `add`, `wmem`, `jmp`.
+const COUNTER: [u16; 14] = [15, 32768, 100, 9, 32768, 32768, 1, 16, 100, 32768, 6, 0, 0, 0];
+
+/// Spreads writes over different pages like game state tends to be.
+fn scatter(i: usize, j: usize) -> usize {
+    (i * 31 + j * 0x7F1) % 0x8000
+}
+
+fn compare(name: &str, array: impl FnOnce() -> Duration, paged: impl FnOnce() -> Duration) {
+    println!("{:<32} {:>12.2?} {:>12.2?}", name, array(), paged());
+}
+
+fn time(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
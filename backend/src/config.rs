@@ -0,0 +1,51 @@
+/// How the VM reacts to behaviour the architecture spec leaves undefined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Fault with an [`Error`](crate::Error).
+    Strict,
+    /// Recover and keep running.
+    Lenient,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmConfig {
+    /// `mod` by zero. Lenient writes 0.
+    pub mod_by_zero: Policy,
+    /// `rmem`/`wmem` addresses with the high bit set. Lenient masks them to 15 bits.
+    pub memory_addresses: Policy,
+    /// Operands above the last register, for both reads and register writes.
+    /// Lenient wraps reads to a 15-bit literal and writes to `dest & 7`.
+    pub operands: Policy,
+    /// `ret` on an empty stack. Lenient halts, as the spec describes.
+    pub empty_stack_ret: Policy,
+    /// Soft limit on the number of stack entries, or `None` for an unbounded stack.
+    pub stack_limit: Option<usize>,
+}
+
+impl VmConfig {
+    pub fn strict() -> Self {
+        Self {
+            mod_by_zero: Policy::Strict,
+            memory_addresses: Policy::Strict,
+            operands: Policy::Strict,
+            empty_stack_ret: Policy::Strict,
+            stack_limit: None,
+        }
+    }
+
+    pub fn lenient() -> Self {
+        Self {
+            mod_by_zero: Policy::Lenient,
+            memory_addresses: Policy::Lenient,
+            operands: Policy::Lenient,
+            empty_stack_ret: Policy::Lenient,
+            stack_limit: None,
+        }
+    }
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self::strict()
+    }
+}
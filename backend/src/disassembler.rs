@@ -1,4 +1,5 @@
 use std::io::{Write, Result};
+use crate::memory::Words;
 
 pub fn disassemble(bin: &[u16], out: &mut impl Write) -> Result<()> {
     let mut pc = 0;
@@ -12,7 +13,7 @@ pub fn disassemble(bin: &[u16], out: &mut impl Write) -> Result<()> {
     Ok(())
 }
 
-pub fn to_assembly_instruction(pc: usize, memory: &[u16]) -> (String, usize) {
+pub fn to_assembly_instruction<M: Words + ?Sized>(pc: usize, memory: &M) -> (String, usize) {
     let opcode = memory[pc];
     let invalid_str = format!("!{:04X}", opcode);
 
@@ -45,11 +46,11 @@ pub fn to_assembly_instruction(pc: usize, memory: &[u16]) -> (String, usize) {
     let mut out = String::new();
 
     if param_count == 0 {
-        out.push_str(&format!("{}", name));
+        out.push_str(name);
         return (out, 1);
     }
 
-    let param_strings = memory[pc + 1..pc + 1 + param_count].iter().map(|&val| {
+    let param_strings = (pc + 1..(pc + 1 + param_count).min(memory.len())).map(|addr| memory[addr]).map(|val| {
         if opcode == 19 {
             match val {
                 0 => "'[NUL]' ".into(),
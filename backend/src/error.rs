@@ -2,13 +2,15 @@ use std::fmt::{Debug, Display, Formatter};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
     IllegalParameterWrite(u16),
     IllegalParameterRead(u16),
     IllegalOpcode(u16),
+    DivisionByZero,
     StackOverflow,
     StackUnderflow,
+    InvalidSnapshot(&'static str),
 }
 
 impl Display for Error {
@@ -17,10 +19,46 @@ impl Display for Error {
             Self::IllegalParameterWrite(p) => write!(f, "illegal register write - 0x{:04X}", p),
             Self::IllegalParameterRead(p) => write!(f, "illegal parameter read - 0x{:04X}", p),
             Self::IllegalOpcode(op) => write!(f, "illegal opcode - 0x{:04X}", op),
+            Self::DivisionByZero => write!(f, "modulo by zero"),
             Self::StackOverflow => write!(f, "stack overflow"),
             Self::StackUnderflow => write!(f, "stack underflow"),
+            Self::InvalidSnapshot(reason) => write!(f, "invalid snapshot - {}", reason),
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+/// An [`Error`] together with the state of the VM at the faulting instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fault {
+    pub error: Error,
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: String,
+    /// Index and raw value of the operand that caused the fault, if any.
+    pub operand: Option<(usize, u16)>,
+    pub registers: [u16; 8],
+    pub sp: usize,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.error)?;
+        writeln!(f, "  at:          {:04X}    {}", self.pc, self.instruction)?;
+        writeln!(f, "  opcode:      0x{:04X}", self.opcode)?;
+
+        if let Some((i, val)) = self.operand {
+            writeln!(f, "  operand:     #{} = 0x{:04X}", i, val)?;
+        }
+
+        writeln!(f, "  registers:   {:04X?}", self.registers)?;
+        write!(f, "  stack size:  {}", self.sp)
+    }
+}
+
+impl std::error::Error for Fault {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
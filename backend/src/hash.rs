@@ -0,0 +1,22 @@
+//! Zobrist-style hashing: a state's hash is the XOR of one hash per non-zero
+//! word, so changing a word only needs the old and new word hashed.
+
+/// Locations past memory, so each part of the state hashes differently.
+pub(crate) const REGISTERS: u64 = 0x8000;
+pub(crate) const PC: u64 = 0x8008;
+pub(crate) const STACK_LEN: u64 = 0x8009;
+pub(crate) const WAITING: u64 = 0x800A;
+pub(crate) const STACK: u64 = 0x10000;
+
+/// Hash contribution of `val` stored at `location`. Zero words contribute
+/// nothing, so empty memory and an empty stack hash to 0.
+pub(crate) fn word(location: u64, val: u64) -> u64 {
+    if val == 0 { 0 } else { splitmix64((location << 32) ^ val) }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
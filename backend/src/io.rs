@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+pub trait InputSource {
+    /// Returns the next input byte, or `None` once the input is exhausted.
+    fn next_byte(&mut self) -> Option<u8>;
+}
+
+pub trait OutputSink {
+    fn write_byte(&mut self, val: u8);
+}
+
+/// Why [`SynacorVM::run_with_io`](crate::SynacorVM::run_with_io) returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Halted,
+    /// The VM is blocked on `in` and the input source is empty. Running again
+    /// resumes from there.
+    InputExhausted,
+}
+
+#[derive(Debug, Default)]
+pub struct StdinSource {
+    buffer: VecDeque<u8>,
+}
+
+impl StdinSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl InputSource for StdinSource {
+    fn next_byte(&mut self) -> Option<u8> {
+        if self.buffer.is_empty() {
+            io::stdout().flush().ok()?;
+
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+
+            self.buffer.extend(line.trim_end_matches(['\r', '\n']).bytes());
+            self.buffer.push_back(b'\n');
+        }
+
+        self.buffer.pop_front()
+    }
+}
+
+/// Reads input line by line from any buffered reader, such as a file or a pipe.
+#[derive(Debug)]
+pub struct ReaderSource<R> {
+    reader: R,
+    buffer: VecDeque<u8>,
+}
+
+impl<R: BufRead> ReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for ReaderSource<R> {
+    fn next_byte(&mut self) -> Option<u8> {
+        if self.buffer.is_empty() {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+
+            self.buffer.extend(line.trim_end_matches(['\r', '\n']).bytes());
+            self.buffer.push_back(b'\n');
+        }
+
+        self.buffer.pop_front()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn write_byte(&mut self, val: u8) {
+        // A closed pipe, e.g. into `head`, shouldn't bring the VM down.
+        io::stdout().write_all(&[val]).ok();
+    }
+}
+
+impl InputSource for VecDeque<u8> {
+    fn next_byte(&mut self) -> Option<u8> {
+        self.pop_front()
+    }
+}
+
+impl OutputSink for Vec<u8> {
+    fn write_byte(&mut self, val: u8) {
+        self.push(val);
+    }
+}
+
+impl OutputSink for String {
+    fn write_byte(&mut self, val: u8) {
+        self.push(val as char);
+    }
+}
+
+/// Feeds a list of lines, each followed by a newline.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedInput {
+    lines: VecDeque<String>,
+    current: VecDeque<u8>,
+    consumed: usize,
+}
+
+impl ScriptedInput {
+    pub fn new<S: Into<String>>(lines: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            lines: lines.into_iter().map(Into::into).collect(),
+            current: VecDeque::new(),
+            consumed: 0,
+        }
+    }
+
+    pub fn push_line(&mut self, line: impl Into<String>) {
+        self.lines.push_back(line.into());
+    }
+
+    /// Number of lines that have been started so far.
+    pub fn consumed(&self) -> usize { self.consumed }
+
+    pub fn remaining(&self) -> usize { self.lines.len() }
+}
+
+impl InputSource for ScriptedInput {
+    fn next_byte(&mut self) -> Option<u8> {
+        if self.current.is_empty() {
+            let line = self.lines.pop_front()?;
+            self.current.extend(line.bytes());
+            self.current.push_back(b'\n');
+            self.consumed += 1;
+        }
+
+        self.current.pop_front()
+    }
+}
+
+/// Forwards output to another sink while copying it to a writer, such as a log file.
+#[derive(Debug)]
+pub struct Tee<S, W> {
+    inner: S,
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<S: OutputSink, W: Write> Tee<S, W> {
+    pub fn new(inner: S, writer: W) -> Self {
+        Self {
+            inner,
+            writer,
+            error: None,
+        }
+    }
+
+    /// The first error the writer returned, after which copying stops.
+    pub fn error(&self) -> Option<&io::Error> { self.error.as_ref() }
+
+    pub fn into_inner(self) -> (S, W) { (self.inner, self.writer) }
+}
+
+impl<S: OutputSink, W: Write> OutputSink for Tee<S, W> {
+    fn write_byte(&mut self, val: u8) {
+        self.inner.write_byte(val);
+
+        if self.error.is_none() {
+            if let Err(e) = self.writer.write_all(&[val]) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
@@ -1,55 +1,93 @@
 pub mod error;
+pub mod config;
 pub mod vm;
 pub mod disassembler;
+pub mod io;
+pub mod session;
+pub mod parser;
+pub mod snapshot;
+pub mod memory;
+mod hash;
 
-pub use error::{Error, Result};
+pub use error::{Error, Fault, Result};
+pub use config::{Policy, VmConfig};
 pub use vm::{SynacorVM, Event};
 pub use disassembler::disassemble;
+pub use io::{InputSource, OutputSink, Exit};
+pub use session::{GameSession, SessionState, STEP_LIMIT};
+pub use parser::{Room, parse_room, parse_inventory, parse_look};
+pub use snapshot::Snapshot;
+pub use memory::{Memory, Words};
 
-#[derive(Debug, Clone)]
-pub struct Stack<T: Default + Copy, const S: usize> {
-    pointer: usize,
-    contents: [T; S],
+#[derive(Debug, Clone, Default)]
+pub struct Stack<T> {
+    contents: Vec<T>,
+    limit: Option<usize>,
+    hash: u64,
 }
 
-impl<T: Default + Copy, const S: usize> Stack<T, S> {
+impl<T> Stack<T> {
     pub fn new() -> Self {
         Self {
-            pointer: 0,
-            contents: [T::default(); S],
+            contents: Vec::new(),
+            limit: None,
+            hash: 0,
         }
     }
 
+    pub fn with_limit(limit: Option<usize>) -> Self {
+        Self {
+            contents: Vec::new(),
+            limit,
+            hash: 0,
+        }
+    }
+
+    pub fn contents(&self) -> &[T] { &self.contents }
+
+    pub fn pointer(&self) -> usize { self.contents.len() }
+
+    pub fn limit(&self) -> Option<usize> { self.limit }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) { self.limit = limit; }
+
+    pub fn len(&self) -> usize {
+        self.contents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contents.is_empty()
+    }
+}
+
+/// Mutation keeps a hash of the contents up to date, so it needs values that
+/// convert to integers.
+impl<T: Copy + Into<u64>> Stack<T> {
     pub fn push(&mut self, val: T) -> Result<()> {
-        if self.pointer >= S {
+        if self.limit.is_some_and(|limit| self.contents.len() >= limit) {
             Err(Error::StackOverflow)
         } else {
-            self.contents[self.pointer] = val;
-            self.pointer += 1;
+            self.hash ^= Self::word_hash(self.contents.len(), val);
+            self.contents.push(val);
             Ok(())
         }
     }
 
     pub fn pop(&mut self) -> Result<T> {
-        if self.pointer == 0 {
-            Err(Error::StackUnderflow)
-        } else {
-            self.pointer -= 1;
-            Ok(std::mem::replace(&mut self.contents[self.pointer], T::default()))
-        }
+        let val = self.contents.pop().ok_or(Error::StackUnderflow)?;
+        self.hash ^= Self::word_hash(self.contents.len(), val);
+        Ok(val)
     }
 
-    pub fn contents(&self) -> &[T] { &self.contents[..self.pointer]}
-
-    pub fn full_contents(&self) -> &[T; S] { &self.contents }
-
-    pub fn pointer(&self) -> usize { self.pointer }
-
-    pub fn full_contents_mut(&mut self) -> &mut [T; S] { &mut self.contents }
+    pub fn set_contents(&mut self, contents: Vec<T>) {
+        self.hash = contents.iter().enumerate().fold(0, |hash, (i, &val)| hash ^ Self::word_hash(i, val));
+        self.contents = contents;
+    }
 
-    pub fn pointer_mut(&mut self) -> &mut usize { &mut self.pointer }
+    /// Hash of the contents, not including the length.
+    pub fn hash(&self) -> u64 { self.hash }
 
-    pub fn len(&self) -> usize {
-        S
+    fn word_hash(position: usize, val: T) -> u64 {
+        hash::word(hash::STACK + position as u64, val.into())
     }
 }
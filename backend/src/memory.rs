@@ -0,0 +1,117 @@
+use std::fmt;
+use std::ops::{Index, Range};
+use std::sync::Arc;
+use crate::hash;
+
+pub const MEMORY_LEN: usize = 0x8000;
+pub const PAGE_LEN: usize = 0x100;
+const PAGE_COUNT: usize = MEMORY_LEN / PAGE_LEN;
+
+type Page = [u16; PAGE_LEN];
+
+/// The VM's 15-bit address space, split into reference-counted pages.
+///
+/// Cloning only copies the page pointers. A page is copied the first time it
+/// is written while shared, so a clone costs just the pages that change after it.
+/// A hash of the contents is kept up to date on every write.
+#[derive(Clone)]
+pub struct Memory {
+    pages: [Arc<Page>; PAGE_COUNT],
+    hash: u64,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        let zero = Arc::new([0; PAGE_LEN]);
+        Self { pages: std::array::from_fn(|_| zero.clone()), hash: 0 }
+    }
+
+    pub fn set(&mut self, addr: usize, val: u16) {
+        if self[addr] == val { return; }
+
+        let word = &mut Arc::make_mut(&mut self.pages[addr / PAGE_LEN])[addr % PAGE_LEN];
+        self.hash ^= hash::word(addr as u64, *word as u64) ^ hash::word(addr as u64, val as u64);
+        *word = val;
+    }
+
+    /// Hash of the contents, without the words in `excluded`, which must not overlap.
+    pub fn hash(&self, excluded: &[Range<usize>]) -> u64 {
+        excluded.iter()
+            .flat_map(|range| range.start.min(MEMORY_LEN)..range.end.min(MEMORY_LEN))
+            .fold(self.hash, |hash, addr| hash ^ hash::word(addr as u64, self[addr] as u64))
+    }
+
+    pub fn len(&self) -> usize { MEMORY_LEN }
+
+    pub fn is_empty(&self) -> bool { false }
+
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.pages.iter().flat_map(|page| page.iter().copied())
+    }
+
+    /// Reads the words in `range`, which must lie within memory.
+    pub fn read(&self, range: Range<usize>) -> impl Iterator<Item = u16> + '_ {
+        assert!(range.end <= MEMORY_LEN, "memory range out of bounds");
+        range.map(|addr| self[addr])
+    }
+
+    /// Writes `values` starting at `addr`, copying only the pages they touch.
+    pub fn write(&mut self, addr: usize, values: &[u16]) {
+        assert!(addr + values.len() <= MEMORY_LEN, "memory range out of bounds");
+
+        for (i, &val) in values.iter().enumerate() {
+            self.set(addr + i, val);
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u16> { self.iter().collect() }
+
+    /// Number of pages not shared with `other`, i.e. the memory one of them
+    /// has copied since they were cloned from each other.
+    pub fn unshared_pages(&self, other: &Memory) -> usize {
+        self.pages.iter().zip(&other.pages).filter(|(a, b)| !Arc::ptr_eq(a, b)).count()
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for Memory {
+    type Output = u16;
+
+    fn index(&self, addr: usize) -> &u16 {
+        &self.pages[addr / PAGE_LEN][addr % PAGE_LEN]
+    }
+}
+
+impl PartialEq for Memory {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.pages.iter().zip(&other.pages).all(|(a, b)| Arc::ptr_eq(a, b) || a == b)
+    }
+}
+
+impl Eq for Memory {}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Word-addressable storage the disassembler can read, such as a binary or [`Memory`].
+pub trait Words: Index<usize, Output = u16> {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl Words for [u16] {
+    fn len(&self) -> usize { <[u16]>::len(self) }
+}
+
+impl Words for Memory {
+    fn len(&self) -> usize { MEMORY_LEN }
+}
@@ -0,0 +1,148 @@
+const PROMPT: &str = "What do you do?";
+const ITEMS_HEADER: &str = "Things of interest here:";
+const INVENTORY_HEADER: &str = "Your inventory:";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Room {
+    pub name: String,
+    pub description: String,
+    pub items: Vec<String>,
+    pub exits: Vec<String>,
+}
+
+/// Parses the last room description in a chunk of game output.
+pub fn parse_room(output: &str) -> Option<Room> {
+    let lines = output.lines().collect::<Vec<_>>();
+    let start = lines.iter().rposition(|l| room_name(l).is_some())?;
+
+    let mut room = Room {
+        name: room_name(lines[start])?.into(),
+        ..Room::default()
+    };
+
+    let mut description = Vec::new();
+    let mut list: Option<&mut Vec<String>> = None;
+
+    for &line in &lines[start + 1..] {
+        let line = line.trim();
+
+        if line == PROMPT {
+            break;
+        } else if line == ITEMS_HEADER {
+            list = Some(&mut room.items);
+        } else if is_exits_header(line) {
+            list = Some(&mut room.exits);
+        } else if let Some(entry) = line.strip_prefix("- ") {
+            match &mut list {
+                Some(list) => list.push(entry.into()),
+                None => description.push(line),
+            }
+        } else if list.is_none() && (!line.is_empty() || !description.is_empty()) {
+            description.push(line);
+        }
+    }
+
+    room.description = description.join("\n").trim().into();
+    Some(room)
+}
+
+/// Parses the response to `inv`.
+pub fn parse_inventory(output: &str) -> Option<Vec<String>> {
+    let mut lines = output.lines().map(str::trim).skip_while(|&l| l != INVENTORY_HEADER);
+    lines.next()?;
+
+    Some(lines
+        .take_while(|&l| l != PROMPT)
+        .filter_map(|l| l.strip_prefix("- "))
+        .map(Into::into)
+        .collect())
+}
+
+/// Parses the response to `look <item>`, which is plain text followed by the prompt.
+pub fn parse_look(output: &str) -> String {
+    strip_prompt(output).trim().into()
+}
+
+pub fn strip_prompt(output: &str) -> &str {
+    let output = output.trim_end();
+    output.strip_suffix(PROMPT).unwrap_or(output)
+}
+
+fn room_name(line: &str) -> Option<&str> {
+    line.trim().strip_prefix("== ")?.strip_suffix(" ==")
+}
+
+fn is_exits_header(line: &str) -> bool {
+    line == "There is 1 exit:" || (line.starts_with("There are ") && line.ends_with(" exits:"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOOTHILLS: &str = "
+== Foothills ==
+You find yourself standing at the base of an enormous mountain.  At its base to the north, there is a massive doorway.  A sign nearby reads \"Keep out!  Definitely no treasure within!\"
+
+Things of interest here:
+- tablet
+
+There are 2 exits:
+- doorway
+- south
+
+What do you do?
+";
+
+    const DARK_PASSAGE: &str = "
+== Dark passage ==
+You are in a dark, narrow passage.
+
+There is 1 exit:
+- west
+
+What do you do?
+";
+
+    #[test]
+    fn room_with_items_and_exits() {
+        assert_eq!(parse_room(FOOTHILLS), Some(Room {
+            name: "Foothills".into(),
+            description: "You find yourself standing at the base of an enormous mountain.  At its base to the north, there is a massive doorway.  A sign nearby reads \"Keep out!  Definitely no treasure within!\"".into(),
+            items: vec!["tablet".into()],
+            exits: vec!["doorway".into(), "south".into()],
+        }));
+    }
+
+    #[test]
+    fn room_with_one_exit_and_no_items() {
+        let room = parse_room(DARK_PASSAGE).unwrap();
+        assert_eq!((room.name.as_str(), room.description.as_str()), ("Dark passage", "You are in a dark, narrow passage."));
+        assert!(room.items.is_empty());
+        assert_eq!(room.exits, ["west"]);
+    }
+
+    #[test]
+    fn last_room_in_a_chunk_wins() {
+        let output = format!("{}go west\n{}", FOOTHILLS, DARK_PASSAGE);
+        assert_eq!(parse_room(&output).unwrap().name, "Dark passage");
+
+        assert_eq!(parse_room("You can't go that way.\n\nWhat do you do?\n"), None);
+    }
+
+    #[test]
+    fn inventory() {
+        let output = "\nYour inventory:\n- tablet\n- empty lantern\n\nWhat do you do?\n";
+        assert_eq!(parse_inventory(output).unwrap(), ["tablet", "empty lantern"]);
+
+        assert_eq!(parse_inventory("\nYour inventory:\n\nWhat do you do?\n").unwrap(), Vec::<String>::new());
+        assert_eq!(parse_inventory("I don't understand; try 'help' for instructions.\n\nWhat do you do?\n"), None);
+    }
+
+    #[test]
+    fn look_at_an_item() {
+        let output = "\nThe tablet seems appropriate for use as a writing surface but is unfortunately blank.  Perhaps you should USE it as a writing surface...\n\nWhat do you do?\n";
+        assert_eq!(parse_look(output), "The tablet seems appropriate for use as a writing surface but is unfortunately blank.  Perhaps you should USE it as a writing surface...");
+        assert_eq!(parse_look("Nothing here.\n"), "Nothing here.");
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use crate::{Event, Fault, SynacorVM};
+
+/// Instructions a tool driving the game lets one command run before giving
+/// up on it, enough for any response the game prints.
+pub const STEP_LIMIT: u64 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionState {
+    WaitingForInput,
+    Halted,
+    Faulted(Fault),
+    /// The last run hit the step limit before the game asked for input.
+    StepLimitReached,
+}
+
+/// Line-oriented wrapper around [`SynacorVM`] for bots and tests.
+#[derive(Debug, Clone)]
+pub struct GameSession {
+    vm: SynacorVM,
+    input: VecDeque<u8>,
+    state: SessionState,
+    step_limit: Option<u64>,
+}
+
+impl GameSession {
+    pub fn new(vm: SynacorVM) -> Self {
+        Self {
+            vm,
+            input: VecDeque::new(),
+            state: SessionState::WaitingForInput,
+            step_limit: None,
+        }
+    }
+
+    pub fn from_binary(bin: &[u16]) -> Self {
+        let mut vm = SynacorVM::new();
+        vm.load_binary(bin);
+        Self::new(vm)
+    }
+
+    /// Runs until the game first asks for input, returning everything it printed.
+    pub fn boot(&mut self) -> String {
+        self.run()
+    }
+
+    /// Sends one line of input and returns the output up to the next prompt.
+    pub fn send(&mut self, command: &str) -> String {
+        self.input.extend(command.bytes());
+        self.input.push_back(b'\n');
+        self.run()
+    }
+
+    /// Runs until the VM blocks on `in` with no buffered input, halts or faults.
+    pub fn run(&mut self) -> String {
+        let mut output = String::new();
+
+        if matches!(self.state, SessionState::Halted | SessionState::Faulted(_)) {
+            return output;
+        }
+
+        let start = self.vm.instructions();
+
+        loop {
+            if self.step_limit.is_some_and(|limit| self.vm.instructions() - start >= limit) {
+                self.state = SessionState::StepLimitReached;
+                break;
+            }
+
+            match self.vm.step() {
+                Err(fault) => {
+                    self.state = SessionState::Faulted(fault);
+                    break;
+                }
+                Ok(Some(Event::Halt)) => {
+                    self.state = SessionState::Halted;
+                    break;
+                }
+                Ok(Some(Event::Output(val))) => output.push(val as char),
+                Ok(Some(Event::Input(_))) => {
+                    let val = match self.input.pop_front() {
+                        Some(val) => val,
+                        None => {
+                            self.state = SessionState::WaitingForInput;
+                            break;
+                        }
+                    };
+
+                    if let Err(fault) = self.vm.write_input(val) {
+                        self.state = SessionState::Faulted(fault);
+                        break;
+                    }
+                }
+                Ok(None) => {}
+            }
+        }
+
+        output
+    }
+
+    pub fn state(&self) -> &SessionState { &self.state }
+
+    pub fn is_halted(&self) -> bool { self.state == SessionState::Halted }
+
+    pub fn fault(&self) -> Option<&Fault> {
+        match &self.state {
+            SessionState::Faulted(fault) => Some(fault),
+            _ => None,
+        }
+    }
+
+    pub fn instructions(&self) -> u64 { self.vm.instructions() }
+
+    /// Limits how many instructions a single [`run`](Self::run) may execute.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) { self.step_limit = limit; }
+
+    pub fn vm(&self) -> &SynacorVM { &self.vm }
+
+    pub fn vm_mut(&mut self) -> &mut SynacorVM { &mut self.vm }
+
+    pub fn into_vm(self) -> SynacorVM { self.vm }
+}
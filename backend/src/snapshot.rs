@@ -0,0 +1,95 @@
+use crate::{Error, Result, SynacorVM};
+use crate::memory::MEMORY_LEN;
+
+/// A copy of everything that changes while a [`SynacorVM`] runs. The
+/// [`VmConfig`](crate::VmConfig) is not part of it, so a snapshot can be
+/// restored into a VM with different policies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub pc: u16,
+    pub registers: [u16; 8],
+    pub stack: Vec<u16>,
+    pub memory: Vec<u16>,
+    pub instructions: u64,
+    /// Destination of the `in` the VM was blocked on.
+    pub waiting: Option<u16>,
+    /// Input consumed since the last newline.
+    pub partial_line: Vec<u8>,
+}
+
+impl SynacorVM {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc(),
+            registers: *self.registers(),
+            stack: self.stack().contents().to_vec(),
+            memory: self.memory().to_vec(),
+            instructions: self.instructions(),
+            waiting: self.waiting(),
+            partial_line: self.partial_line().to_vec(),
+        }
+    }
+
+    /// Restores a snapshot after validating it, leaving the VM untouched on error.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        snapshot.validate(self.stack().limit())?;
+
+        *self.pc_mut() = snapshot.pc;
+        *self.registers_mut() = snapshot.registers;
+        self.stack_mut().set_contents(snapshot.stack.clone());
+        self.memory_mut().write(0, &snapshot.memory);
+        *self.instructions_mut() = snapshot.instructions;
+        self.restore_input(snapshot.waiting, snapshot.partial_line.clone());
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// Checks that the snapshot describes a valid machine, with an optional stack limit.
+    pub fn validate(&self, stack_limit: Option<usize>) -> Result<()> {
+        let invalid = |reason| Err(Error::InvalidSnapshot(reason));
+
+        if self.memory.len() != MEMORY_LEN { return invalid("memory must be 0x8000 words"); }
+        if self.pc as usize >= MEMORY_LEN { return invalid("PC out of range"); }
+        if stack_limit.is_some_and(|limit| self.stack.len() > limit) { return invalid("stack exceeds the stack limit"); }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("snapshots always serialize")
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let snapshot: Snapshot = bincode::deserialize(bytes).map_err(|_| Error::InvalidSnapshot("could not decode snapshot"))?;
+        snapshot.validate(None)?;
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, VmConfig};
+
+    #[test]
+    fn round_trip_with_values_above_15_bits() {
+        let mut vm = SynacorVM::with_config(VmConfig::strict());
+        // `rmem r0 #1` loads its own 0x8000 operand, then `push r0` and `halt`.
+        vm.load_binary(&[15, 0x8000, 1, 2, 0x8000, 0]);
+        while vm.step().unwrap() != Some(Event::Halt) {}
+
+        let snapshot = vm.snapshot();
+        assert_eq!((snapshot.registers[0], &snapshot.stack[..]), (0x8000, &[0x8000][..]));
+
+        let mut restored = SynacorVM::with_config(VmConfig::strict());
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+
+        #[cfg(feature = "serde")]
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+    }
+}
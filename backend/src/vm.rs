@@ -1,6 +1,7 @@
-use crate::{Result, Error, Stack};
-
-pub const STACK_LEN: usize = 0x1000;
+use std::ops::Range;
+use crate::{Result, Error, Fault, Memory, Stack, Policy, VmConfig};
+use crate::{disassembler, hash};
+use crate::io::{Exit, InputSource, OutputSink};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event {
@@ -11,35 +12,107 @@ pub enum Event {
 
 #[derive(Debug, Clone)]
 pub struct SynacorVM {
-    memory: [u16; 0x8000],
+    memory: Memory,
     registers: [u16; 8],
-    stack: Stack<u16, STACK_LEN>,
+    stack: Stack<u16>,
     pc: u16,
+    instructions: u64,
+    config: VmConfig,
+    operand: Option<usize>,
+    /// Destination of the `in` the VM is blocked on.
+    waiting: Option<u16>,
+    /// Input consumed since the last newline.
+    line: Vec<u8>,
+    hash_exclusions: Vec<Range<usize>>,
 }
 
 impl SynacorVM {
     pub fn new() -> Self {
+        Self::with_config(VmConfig::default())
+    }
+
+    pub fn with_config(config: VmConfig) -> Self {
         Self {
-            memory: [0; 0x8000],
+            memory: Memory::new(),
             registers: [0; 8],
-            stack: Stack::new(),
+            stack: Stack::with_limit(config.stack_limit),
             pc: 0,
+            instructions: 0,
+            config,
+            operand: None,
+            waiting: None,
+            line: Vec::new(),
+            hash_exclusions: Vec::new(),
         }
     }
 
     pub fn load_binary(&mut self, bin: &[u16]) {
-        for (i, &val) in bin.iter().enumerate() {
-            self.memory[i] = val;
+        self.memory.write(0, &bin[..bin.len().min(self.memory.len())]);
+    }
+
+    /// Executes one instruction. While blocked on `in`, nothing is executed and
+    /// the input event is returned again until [`write_input`](Self::write_input).
+    pub fn step(&mut self) -> Result<Option<Event>, Fault> {
+        if let Some(dest) = self.waiting {
+            return Ok(Some(Event::Input(dest)));
+        }
+
+        let pc = self.pc;
+        self.operand = None;
+
+        let status = self.execute().map_err(|error| self.fault(pc, error))?;
+        self.instructions += 1;
+        Ok(status)
+    }
+
+    /// Completes the `in` the VM is blocked on. Does nothing if it isn't waiting.
+    pub fn write_input(&mut self, val: u8) -> Result<(), Fault> {
+        let dest = match self.waiting.take() {
+            Some(dest) => dest,
+            None => return Ok(()),
+        };
+
+        let pc = self.pc.wrapping_sub(2) & 0x7FFF;
+        self.write_register(dest, val as u16).map_err(|error| self.fault(pc, error))?;
+
+        if val == b'\n' { self.line.clear(); } else { self.line.push(val); }
+        Ok(())
+    }
+
+    pub(crate) fn restore_input(&mut self, waiting: Option<u16>, line: Vec<u8>) {
+        self.waiting = waiting;
+        self.line = line;
+    }
+
+    /// Stops waiting for input, skipping the rest of the `in` instruction.
+    pub fn cancel_input(&mut self) { self.waiting = None; }
+
+    pub fn waiting(&self) -> Option<u16> { self.waiting }
+
+    /// Input consumed since the last newline.
+    pub fn partial_line(&self) -> &[u8] { &self.line }
+
+    pub fn run_with_io(&mut self, input: &mut impl InputSource, output: &mut impl OutputSink) -> Result<Exit, Fault> {
+        loop {
+            match self.step()? {
+                Some(Event::Halt) => return Ok(Exit::Halted),
+                Some(Event::Output(val)) => output.write_byte(val),
+                Some(Event::Input(_)) => match input.next_byte() {
+                    Some(val) => self.write_input(val)?,
+                    None => return Ok(Exit::InputExhausted),
+                },
+                None => {}
+            }
         }
     }
 
-    pub fn step(&mut self) -> Result<Option<Event>> {
+    fn execute(&mut self) -> Result<Option<Event>> {
         let opcode = self.read_pc();
 
         match opcode {
             0 => return Ok(Some(Event::Halt)), // halt
             1 => { // set
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let val = self.read_param_value()?;
                 self.write_register(reg, val)?;
             }
@@ -48,18 +121,23 @@ impl SynacorVM {
                 self.stack.push(val)?;
             }
             3 => { // pop
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let val = self.stack.pop()?;
-                self.write_register(reg, val)?;
+
+                // Put the value back on a fault, so resuming retries the same `pop`.
+                if let Err(error) = self.write_register(reg, val) {
+                    self.stack.push(val)?;
+                    return Err(error);
+                }
             }
             4 => { // eq
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let a = self.read_param_value()?;
                 let b = self.read_param_value()?;
                 self.write_register(reg, (a == b) as u16)?;
             }
             5 => { // gt
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let a = self.read_param_value()?;
                 let b = self.read_param_value()?;
                 self.write_register(reg, (a > b) as u16)?;
@@ -80,66 +158,82 @@ impl SynacorVM {
                 }
             }
             9 => { // add
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let a = self.read_param_value()?;
                 let b = self.read_param_value()?;
-                self.write_register(reg, (a + b) & 0x7FFF)?;
+                self.write_register(reg, a.wrapping_add(b) & 0x7FFF)?;
             }
             10 => { // mult
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let a = self.read_param_value()?;
                 let b = self.read_param_value()?;
                 self.write_register(reg, a.wrapping_mul(b) & 0x7FFF)?;
             }
             11 => { // mod
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let a = self.read_param_value()?;
                 let b = self.read_param_value()?;
-                self.write_register(reg, a % b)?;
+
+                let val = match (b, self.config.mod_by_zero) {
+                    (0, Policy::Strict) => {
+                        self.operand = Some(2);
+                        return Err(Error::DivisionByZero);
+                    }
+                    (0, Policy::Lenient) => 0,
+                    _ => a % b,
+                };
+                self.write_register(reg, val)?;
             }
             12 => { // and
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let a = self.read_param_value()?;
                 let b = self.read_param_value()?;
                 self.write_register(reg, a & b)?;
             }
             13 => { // or
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let a = self.read_param_value()?;
                 let b = self.read_param_value()?;
                 self.write_register(reg, a | b)?;
             }
             14 => { // not
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let val = self.read_param_value()?;
                 self.write_register(reg, !val & 0x7FFF)?;
             }
             15 => { // rmem
-                let reg = self.read_pc();
+                let reg = self.read_operand();
                 let addr = self.read_param_value()?;
-                if addr & 0x8000 != 0 { return Err(Error::IllegalParameterRead(addr)); }
+                let addr = self.check_address(addr, 1).map_err(Error::IllegalParameterRead)?;
 
                 self.write_register(reg, self.memory[addr as usize])?;
             }
             16 => { // wmem
                 let addr = self.read_param_value()?;
                 let val = self.read_param_value()?;
-                if addr & 0x8000 != 0 { return Err(Error::IllegalParameterWrite(addr)); }
+                let addr = self.check_address(addr, 0).map_err(Error::IllegalParameterWrite)?;
 
-                self.memory[addr as usize] = val;
+                self.memory.set(addr as usize, val);
             }
             17 => { // call
                 let addr = self.read_param_value()?;
                 self.stack.push(self.pc)?;
                 self.pc = addr;
             }
-            18 => self.pc = self.stack.pop()?, // ret
+            18 => { // ret
+                if self.stack.is_empty() && self.config.empty_stack_ret == Policy::Lenient {
+                    return Ok(Some(Event::Halt));
+                }
+
+                self.pc = self.stack.pop()?;
+            }
             19 => { // out
                 let val = self.read_param_value()?;
                 return Ok(Some(Event::Output(val as u8)));
             }
             20 => { // in
-                let reg = self.read_pc();
+                let reg = self.read_operand();
+                self.waiting = Some(reg);
                 return Ok(Some(Event::Input(reg)));
             }
             21 => {} // noop
@@ -149,49 +243,193 @@ impl SynacorVM {
         Ok(None)
     }
 
-    pub fn write_input(&mut self, dest: u16, val: u8) -> Result<()> {
-        self.write_register(dest, val as u16)
-    }
-
     fn read_pc(&mut self) -> u16 {
         let val = self.memory[self.pc as usize];
         self.pc = (self.pc + 1) & 0x7FFF;
         val
     }
 
+    fn read_operand(&mut self) -> u16 {
+        self.operand = Some(self.operand.map_or(0, |i| i + 1));
+        self.read_pc()
+    }
+
     fn read_param_value(&mut self) -> Result<u16> {
-        let val = self.read_pc();
+        let val = self.read_operand();
         if val & 0x8000 == 0 {
             Ok(val)
         } else if val & 0x7FF8 == 0 {
             Ok(self.registers[(val & 0x7FFF) as usize])
+        } else if self.config.operands == Policy::Lenient {
+            Ok(val & 0x7FFF)
         } else {
             Err(Error::IllegalParameterRead(val))
         }
     }
 
+    fn check_address(&mut self, addr: u16, operand: usize) -> Result<u16, u16> {
+        if addr & 0x8000 == 0 {
+            Ok(addr)
+        } else if self.config.memory_addresses == Policy::Lenient {
+            Ok(addr & 0x7FFF)
+        } else {
+            self.operand = Some(operand);
+            Err(addr)
+        }
+    }
+
     fn write_register(&mut self, dest: u16, val: u16) -> Result<()> {
-        if dest & 0x8000 == 0 || dest & 0x7FF8 != 0 {
-            Err(Error::IllegalParameterWrite(val))
+        if (dest & 0x8000 == 0 || dest & 0x7FF8 != 0) && self.config.operands == Policy::Strict {
+            self.operand = Some(0);
+            Err(Error::IllegalParameterWrite(dest))
         } else {
-            self.registers[(dest & 0x7FFF) as usize] = val;
+            self.registers[(dest & 0x7) as usize] = val;
             Ok(())
         }
     }
 
+    fn fault(&self, pc: u16, error: Error) -> Fault {
+        let operand = match error {
+            Error::IllegalParameterRead(_) | Error::IllegalParameterWrite(_) | Error::DivisionByZero => self.operand,
+            _ => None,
+        };
+
+        Fault {
+            opcode: self.memory[pc as usize],
+            instruction: disassembler::to_assembly_instruction(pc as usize, &self.memory).0,
+            operand: operand.map(|i| (i, self.memory[(pc as usize + 1 + i) & 0x7FFF])),
+            registers: self.registers,
+            sp: self.stack.pointer(),
+            error,
+            pc,
+        }
+    }
+
+    /// A 64-bit hash of memory, registers, stack, PC and input wait state, for
+    /// telling game states apart. Memory and the stack keep their parts up to
+    /// date as they change, so this doesn't rehash the whole machine.
+    pub fn state_hash(&self) -> u64 {
+        let registers = self.registers.iter().enumerate()
+            .fold(0, |hash, (i, &r)| hash ^ hash::word(hash::REGISTERS + i as u64, r as u64));
+
+        self.memory.hash(&self.hash_exclusions)
+            ^ self.stack.hash()
+            ^ registers
+            ^ hash::word(hash::PC, self.pc as u64)
+            ^ hash::word(hash::STACK_LEN, self.stack.len() as u64)
+            ^ hash::word(hash::WAITING, self.waiting.map_or(0, |dest| dest as u64 + 1))
+    }
+
+    /// Memory regions left out of [`state_hash`](Self::state_hash), such as
+    /// counters that differ between otherwise identical states.
+    /// Overlapping ranges are merged, so each word is only left out once.
+    pub fn set_hash_exclusions(&mut self, mut exclusions: Vec<Range<usize>>) {
+        exclusions.retain(|range| !range.is_empty());
+        exclusions.sort_by_key(|range| range.start);
+
+        self.hash_exclusions.clear();
+        for range in exclusions {
+            match self.hash_exclusions.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => self.hash_exclusions.push(range),
+            }
+        }
+    }
+
+    pub fn hash_exclusions(&self) -> &[Range<usize>] { &self.hash_exclusions }
+
+    pub fn config(&self) -> &VmConfig { &self.config }
+
+    pub fn set_config(&mut self, config: VmConfig) {
+        self.stack.set_limit(config.stack_limit);
+        self.config = config;
+    }
+
     pub fn pc(&self) -> u16 { self.pc }
 
-    pub fn stack(&self) -> &Stack<u16, STACK_LEN> { &self.stack }
+    pub fn instructions(&self) -> u64 { self.instructions }
 
-    pub fn memory(&self) -> &[u16; 0x8000] { &self.memory }
+    pub fn stack(&self) -> &Stack<u16> { &self.stack }
+
+    pub fn memory(&self) -> &Memory { &self.memory }
 
     pub fn registers(&self) -> &[u16; 8] { &self.registers }
 
     pub fn pc_mut(&mut self) -> &mut u16 { &mut self.pc }
 
-    pub fn stack_mut(&mut self) -> &mut Stack<u16, STACK_LEN> { &mut self.stack }
+    pub fn instructions_mut(&mut self) -> &mut u64 { &mut self.instructions }
+
+    pub fn stack_mut(&mut self) -> &mut Stack<u16> { &mut self.stack }
 
-    pub fn memory_mut(&mut self) -> &mut [u16; 0x8000] { &mut self.memory }
+    pub fn memory_mut(&mut self) -> &mut Memory { &mut self.memory }
 
     pub fn registers_mut(&mut self) -> &mut [u16; 8] { &mut self.registers }
-}
\ No newline at end of file
+}
+
+impl Default for SynacorVM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_wraps_values_read_from_operands() {
+        for config in [VmConfig::strict(), VmConfig::lenient()] {
+            let mut vm = SynacorVM::with_config(config);
+            // `rmem r0 #1` loads its own 0x8000 operand, then `add r1 r0 r0` and `halt`.
+            vm.load_binary(&[15, 0x8000, 1, 9, 0x8001, 0x8000, 0x8000, 0]);
+
+            while vm.step().unwrap() != Some(Event::Halt) {}
+            assert_eq!(vm.registers()[..2], [0x8000, 0]);
+        }
+    }
+
+    /// `state_hash` computed from scratch instead of from the kept-up-to-date parts.
+    fn full_hash(vm: &SynacorVM) -> u64 {
+        let excluded = |addr: usize| vm.hash_exclusions().iter().any(|range| range.contains(&addr));
+        let memory = vm.memory().iter().enumerate()
+            .filter(|&(addr, _)| !excluded(addr))
+            .fold(0, |hash, (addr, val)| hash ^ hash::word(addr as u64, val as u64));
+        let stack = vm.stack().contents().iter().enumerate()
+            .fold(0, |hash, (i, &val)| hash ^ hash::word(hash::STACK + i as u64, val as u64));
+        let registers = vm.registers().iter().enumerate()
+            .fold(0, |hash, (i, &r)| hash ^ hash::word(hash::REGISTERS + i as u64, r as u64));
+
+        memory ^ stack ^ registers
+            ^ hash::word(hash::PC, vm.pc() as u64)
+            ^ hash::word(hash::STACK_LEN, vm.stack().len() as u64)
+            ^ hash::word(hash::WAITING, vm.waiting().map_or(0, |dest| dest as u64 + 1))
+    }
+
+    #[test]
+    fn state_hash_matches_recompute() {
+        let mut vm = SynacorVM::new();
+        // `push #7`, `wmem #20 #9`, `in r0`, then `halt`.
+        vm.load_binary(&[2, 7, 16, 20, 9, 20, 0x8000, 0]);
+        vm.set_hash_exclusions(vec![30..34, 0..0, 32..40]);
+        assert_eq!(vm.state_hash(), full_hash(&vm));
+
+        let check = |vm: &mut SynacorVM, change: fn(&mut SynacorVM)| {
+            let before = vm.clone();
+            change(vm);
+            assert_eq!(vm.state_hash(), full_hash(vm));
+            assert_eq!(before.state_hash(), full_hash(&before));
+        };
+
+        check(&mut vm, |vm| vm.memory_mut().set(0x100, 5));
+        check(&mut vm, |vm| vm.memory_mut().set(0x100, 0));
+        check(&mut vm, |vm| vm.memory_mut().set(35, 1));
+        check(&mut vm, |vm| vm.memory_mut().write(0x1FE, &[1, 2, 3, 0]));
+        check(&mut vm, |vm| vm.stack_mut().push(3).unwrap());
+        check(&mut vm, |vm| { vm.stack_mut().pop().unwrap(); });
+        check(&mut vm, |vm| vm.stack_mut().set_contents(vec![4, 0, 4]));
+        check(&mut vm, |vm| vm.registers_mut()[3] = 9);
+        check(&mut vm, |vm| { while vm.step().unwrap().is_none() {} });
+        check(&mut vm, |vm| vm.cancel_input());
+        check(&mut vm, |vm| vm.set_hash_exclusions(Vec::new()));
+    }
+}
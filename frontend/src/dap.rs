@@ -0,0 +1,682 @@
+//! Debug Adapter Protocol server over stdio.
+//!
+//! The disassembly of the whole memory is exposed as a single virtual source,
+//! one instruction per line. Memory references are word addresses, while
+//! `readMemory`/`writeMemory` offsets and counts are in bytes of the
+//! little-endian view of memory.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use backend::{disassembler, Event};
+use serde_json::{json, Value};
+use crate::{split_words, TerminalVM, COMMAND_PREFIX};
+
+const STEPS_PER_POLL: usize = 0x4000;
+const MEMORY_ROW_LEN: usize = 8;
+const REGISTERS_REF: u64 = 1;
+const STACK_REF: u64 = 2;
+const MEMORY_REF: u64 = 3;
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn serve(term: &mut TerminalVM) -> io::Result<()> {
+    colored::control::set_override(false);
+    term.messages = Some(Vec::new());
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+
+        while let Ok(Some(msg)) = read_message(&mut reader) {
+            if tx.send(msg).is_err() { break; }
+        }
+    });
+
+    let result = DapServer::new(term, rx).run();
+    term.messages = None;
+    colored::control::unset_override();
+    result
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RunMode {
+    Continue,
+    Over { pc: u16, sp: usize },
+    Out { sp: usize },
+}
+
+impl RunMode {
+    /// Whether executing `opcode` with `sp_before` stack entries, which left
+    /// the VM at `pc` with `sp` entries, completes the step.
+    fn finished(self, opcode: u16, sp_before: usize, pc: u16, sp: usize) -> bool {
+        match self {
+            RunMode::Continue => false,
+            RunMode::Over { pc: target, sp: depth } => pc == target && sp == depth,
+            RunMode::Out { sp: depth } => opcode == 18 && sp_before <= depth,
+        }
+    }
+}
+
+struct DapServer<'a> {
+    term: &'a mut TerminalVM,
+    requests: Receiver<Value>,
+    seq: u64,
+    listing: Vec<u16>,
+    breakpoints: Vec<u16>,
+    run_mode: Option<RunMode>,
+    stop_on_entry: bool,
+    output: Vec<u8>,
+    halted: bool,
+}
+
+impl<'a> DapServer<'a> {
+    fn new(term: &'a mut TerminalVM, requests: Receiver<Value>) -> Self {
+        Self {
+            term,
+            requests,
+            seq: 0,
+            listing: Vec::new(),
+            breakpoints: Vec::new(),
+            run_mode: None,
+            stop_on_entry: false,
+            output: Vec::new(),
+            halted: false,
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let msg = if self.is_running() {
+                match self.requests.try_recv() {
+                    Ok(msg) => Some(msg),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match self.requests.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if let Some(msg) = msg {
+                if !self.handle_request(&msg)? { return Ok(()); }
+            }
+
+            if self.is_running() {
+                self.run_chunk()?;
+            }
+
+            self.flush_messages()?;
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.run_mode.is_some() && !self.halted && (self.term.vm.waiting().is_none() || !self.term.input_queue.is_empty())
+    }
+
+    fn handle_request(&mut self, msg: &Value) -> io::Result<bool> {
+        let command = msg["command"].as_str().unwrap_or_default();
+        let args = &msg["arguments"];
+
+        let result = match command {
+            "initialize" => {
+                self.respond(msg, Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsWriteMemoryRequest": true,
+                })))?;
+                return self.send_event("initialized", json!({})).map(|_| true);
+            }
+            "launch" => self.launch(args),
+            "attach" => {
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                self.build_listing();
+                Ok(json!({}))
+            }
+            "configurationDone" => {
+                self.respond(msg, Ok(json!({})))?;
+
+                if self.stop_on_entry {
+                    self.send_stopped("entry", None)?;
+                } else {
+                    self.run_mode = Some(RunMode::Continue);
+                }
+                return Ok(true);
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "Synacor VM" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY_REF, "indexedVariables": 0x8000 / MEMORY_ROW_LEN, "expensive": true },
+            ] })),
+            "variables" => Ok(self.variables(args)),
+            "source" => Ok(json!({ "content": self.listing_text() })),
+            "continue" => {
+                self.run_mode = Some(RunMode::Continue);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                let pc = self.term.vm.pc();
+                if self.term.vm.memory()[pc as usize] == 17 {
+                    self.run_mode = Some(RunMode::Over { pc: (pc + 2) & 0x7FFF, sp: self.term.vm.stack().pointer() });
+                    Ok(json!({}))
+                } else {
+                    return self.single_step(msg);
+                }
+            }
+            "stepIn" => return self.single_step(msg),
+            "stepOut" => {
+                self.run_mode = Some(RunMode::Out { sp: self.term.vm.stack().pointer() });
+                Ok(json!({}))
+            }
+            "pause" => {
+                self.respond(msg, Ok(json!({})))?;
+                self.run_mode = None;
+                self.send_stopped("pause", None)?;
+                return Ok(true);
+            }
+            "readMemory" => self.read_memory(args),
+            "writeMemory" => self.write_memory(args),
+            "evaluate" => self.evaluate(args),
+            "disconnect" => {
+                self.respond(msg, Ok(json!({})))?;
+                return Ok(false);
+            }
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+
+        self.respond(msg, result)?;
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str().ok_or("no program provided")?;
+        let buf = fs::read(program).map_err(|e| e.to_string())?;
+
+        if args["loadState"].as_bool().unwrap_or(false) {
+            self.term.load_state_buf(&buf)?;
+        } else {
+            self.term.load_binary(&crate::to_u16_vec(&buf));
+        }
+
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.build_listing();
+        Ok(json!({}))
+    }
+
+    fn single_step(&mut self, msg: &Value) -> io::Result<bool> {
+        self.respond(msg, Ok(json!({})))?;
+        self.run_mode = None;
+
+        match self.execute() {
+            Ok(_) if self.halted => {}
+            Ok(_) => self.send_stopped("step", None)?,
+            Err(e) => self.send_stopped("exception", Some(e))?,
+        }
+
+        Ok(true)
+    }
+
+    fn run_chunk(&mut self) -> io::Result<()> {
+        for _ in 0..STEPS_PER_POLL {
+            let mode = match self.run_mode {
+                Some(mode) => mode,
+                None => break,
+            };
+
+            let sp = self.term.vm.stack().pointer();
+            let opcode = self.term.vm.memory()[self.term.vm.pc() as usize];
+
+            let reason = match self.execute() {
+                Err(e) => {
+                    self.run_mode = None;
+                    self.send_stopped("exception", Some(e))?;
+                    break;
+                }
+                Ok(false) => break,
+                Ok(true) => {
+                    let pc = self.term.vm.pc();
+
+                    if self.breakpoints.contains(&pc) {
+                        Some("breakpoint")
+                    } else if mode.finished(opcode, sp, pc, self.term.vm.stack().pointer()) {
+                        Some("step")
+                    } else {
+                        None
+                    }
+                }
+            };
+
+            if self.halted { break; }
+
+            if let Some(reason) = reason {
+                self.run_mode = None;
+                self.send_stopped(reason, None)?;
+                break;
+            }
+        }
+
+        self.flush_output()
+    }
+
+    /// Executes one instruction, returning whether the VM made progress.
+    fn execute(&mut self) -> Result<bool, String> {
+        if self.halted {
+            return Ok(false);
+        }
+
+        if self.term.vm.waiting().is_some() {
+            match self.term.consume_input() {
+                Some(Ok(())) => {}
+                Some(Err(fault)) => {
+                    *self.term.vm.pc_mut() = fault.pc;
+                    return Err(fault.to_string());
+                }
+                None => return Ok(false),
+            }
+        }
+
+        self.term.pc_history.push(self.term.vm.pc());
+
+        match self.term.vm.step() {
+            Err(fault) => {
+                *self.term.vm.pc_mut() = fault.pc;
+                return Err(fault.to_string());
+            }
+            Ok(Some(Event::Halt)) => {
+                self.halted = true;
+                self.run_mode = None;
+                self.flush_output().map_err(|e| e.to_string())?;
+                self.send_event("terminated", json!({})).map_err(|e| e.to_string())?;
+            }
+            Ok(Some(Event::Output(val))) => {
+                self.output.push(val);
+                self.term.response.push(val as char);
+                if val == b'\n' { self.flush_output().map_err(|e| e.to_string())?; }
+            }
+            Ok(Some(Event::Input(_))) => {
+                if self.term.input_queue.is_empty() {
+                    self.term.finish_response();
+                    self.flush_output().map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(None) => {}
+        }
+
+        Ok(true)
+    }
+
+    fn evaluate(&mut self, args: &Value) -> Result<Value, String> {
+        let expression = args["expression"].as_str().ok_or("no expression provided")?.trim().to_string();
+
+        if args["context"].as_str() != Some("repl") {
+            return Err("only debug console input is supported".into());
+        }
+
+        if expression.starts_with(COMMAND_PREFIX) {
+            self.term.run_command(split_words(expression));
+        } else {
+            self.term.write_input(&expression);
+            self.term.saved = false;
+        }
+
+        Ok(json!({ "result": "", "variablesReference": 0 }))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        self.breakpoints.clear();
+
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints = requested.iter().map(|bp| {
+            let line = bp["line"].as_u64().unwrap_or(0) as usize;
+            match line.checked_sub(1).and_then(|i| self.listing.get(i)) {
+                Some(&addr) => {
+                    self.breakpoints.push(addr);
+                    json!({ "verified": true, "line": line })
+                }
+                None => json!({ "verified": false, "line": line, "message": "no instruction on this line" }),
+            }
+        }).collect::<Vec<_>>();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(&self) -> Value {
+        let memory = self.term.vm.memory();
+        let pc = self.term.vm.pc();
+
+        let (assembly, _) = disassembler::to_assembly_instruction(pc as usize, memory);
+        let mut frames = vec![self.frame(0, pc, assembly)];
+
+        let returns = self.term.vm.stack().contents().iter().rev()
+            .filter(|&&val| (2..0x8000).contains(&val) && memory[val as usize - 2] == 17);
+
+        for (i, &ret) in returns.enumerate() {
+            let (assembly, _) = disassembler::to_assembly_instruction(ret as usize - 2, memory);
+            frames.push(self.frame(i + 1, ret - 2, assembly));
+        }
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn frame(&self, id: usize, addr: u16, assembly: String) -> Value {
+        json!({
+            "id": id,
+            "name": format!("{:04X}    {}", addr, assembly),
+            "source": { "name": "disassembly", "sourceReference": 1 },
+            "line": self.line_of(addr),
+            "column": 1,
+            "instructionPointerReference": addr.to_string(),
+        })
+    }
+
+    fn variables(&self, args: &Value) -> Value {
+        let vm = &self.term.vm;
+
+        let variables = match args["variablesReference"].as_u64() {
+            Some(REGISTERS_REF) => {
+                let mut vars = vm.registers().iter().enumerate()
+                    .map(|(i, &val)| word_variable(&format!("r{}", i), val))
+                    .collect::<Vec<_>>();
+
+                vars.push(word_variable("pc", vm.pc()));
+                vars.push(json!({ "name": "sp", "value": vm.stack().pointer().to_string(), "variablesReference": 0 }));
+                vars
+            }
+            Some(STACK_REF) => vm.stack().contents().iter().enumerate().rev()
+                .map(|(i, &val)| word_variable(&format!("{:04X}", i), val))
+                .collect(),
+            Some(MEMORY_REF) => {
+                let rows = 0x8000 / MEMORY_ROW_LEN;
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = args["count"].as_u64().map_or(rows, |c| c as usize);
+
+                (start..start.saturating_add(count).min(rows)).map(|row| {
+                    let addr = row * MEMORY_ROW_LEN;
+                    let words = vm.memory().read(addr..addr + MEMORY_ROW_LEN)
+                        .map(|val| format!("{:04X}", val))
+                        .collect::<Vec<_>>();
+
+                    json!({
+                        "name": format!("{:04X}", addr),
+                        "value": words.join(" "),
+                        "variablesReference": 0,
+                        "memoryReference": addr.to_string(),
+                    })
+                }).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        json!({ "variables": variables })
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let (start, count) = byte_range(args)?;
+        let count = count.unwrap_or(0) as usize;
+        let memory = self.term.vm.memory();
+
+        let end = start.saturating_add(count).min(0x10000);
+        let bytes = (start..end).map(|i| {
+            let word = memory[i / 2];
+            if i % 2 == 0 { word as u8 } else { (word >> 8) as u8 }
+        }).collect::<Vec<_>>();
+
+        Ok(json!({
+            "address": start.to_string(),
+            "data": encode_base64(&bytes),
+            "unreadableBytes": count - bytes.len(),
+        }))
+    }
+
+    fn write_memory(&mut self, args: &Value) -> Result<Value, String> {
+        let (start, _) = byte_range(args)?;
+        let data = decode_base64(args["data"].as_str().unwrap_or_default()).ok_or("invalid data")?;
+
+        if start + data.len() > 0x10000 {
+            return Err("write out of range".into());
+        }
+
+        let memory = self.term.vm.memory_mut();
+        for (i, &b) in data.iter().enumerate() {
+            let addr = start + i;
+            let word = memory[addr / 2];
+            memory.set(addr / 2, if addr % 2 == 0 { (word & 0xFF00) | b as u16 } else { (word & 0x00FF) | ((b as u16) << 8) });
+        }
+
+        Ok(json!({ "bytesWritten": data.len() }))
+    }
+
+    fn build_listing(&mut self) {
+        let memory = self.term.vm.memory();
+        self.listing.clear();
+
+        let mut addr = 0;
+        while addr + 4 <= memory.len() {
+            self.listing.push(addr as u16);
+            addr += disassembler::to_assembly_instruction(addr, memory).1;
+        }
+    }
+
+    fn listing_text(&self) -> String {
+        self.listing.iter().map(|&addr| {
+            let (assembly, _) = disassembler::to_assembly_instruction(addr as usize, self.term.vm.memory());
+            format!("{:04X}    {}\n", addr, assembly)
+        }).collect()
+    }
+
+    fn line_of(&self, addr: u16) -> usize {
+        match self.listing.binary_search(&addr) {
+            Ok(i) => i + 1,
+            Err(i) => i.max(1),
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+
+        let text = String::from_utf8_lossy(&self.output).into_owned();
+        self.output.clear();
+        self.send_event("output", json!({ "category": "stdout", "output": text }))
+    }
+
+    fn flush_messages(&mut self) -> io::Result<()> {
+        let messages = self.term.messages.as_mut().map(std::mem::take).unwrap_or_default();
+
+        for msg in messages {
+            self.send_event("output", json!({ "category": "console", "output": msg + "\n" }))?;
+        }
+
+        Ok(())
+    }
+
+    fn send_stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.flush_output()?;
+        self.send_event("stopped", json!({
+            "reason": reason,
+            "threadId": 1,
+            "text": text,
+            "allThreadsStopped": true,
+        }))
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+
+        match result {
+            Ok(body) => msg["body"] = body,
+            Err(e) => msg["message"] = e.into(),
+        }
+
+        self.send(msg)
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut msg: Value) -> io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = self.seq.into();
+
+        let content = msg.to_string();
+        let mut out = io::stdout().lock();
+        write!(out, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+        out.flush()
+    }
+}
+
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim();
+        if line.is_empty() { break; }
+
+        if let Some(len) = line.strip_prefix("Content-Length:") {
+            length = len.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut buf = vec![0; length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?];
+    reader.read_exact(&mut buf)?;
+    serde_json::from_slice(&buf).map(Some).map_err(io::Error::from)
+}
+
+fn word_variable(name: &str, val: u16) -> Value {
+    json!({
+        "name": name,
+        "value": format!("{:04X}", val),
+        "variablesReference": 0,
+        "memoryReference": if val < 0x8000 { Some(val.to_string()) } else { None },
+    })
+}
+
+fn byte_range(args: &Value) -> Result<(usize, Option<u64>), String> {
+    let reference = args["memoryReference"].as_str().ok_or("no memory reference provided")?;
+    let addr = match reference.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => reference.parse(),
+    }.map_err(|_| "invalid memory reference")?;
+
+    let start = i64::try_from(addr).ok()
+        .and_then(|addr| addr.checked_mul(2))
+        .and_then(|start| start.checked_add(args["offset"].as_i64().unwrap_or(0)))
+        .filter(|start| (0..0x10000).contains(start))
+        .ok_or("address out of range")?;
+
+    Ok((start as usize, args["count"].as_u64()))
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+
+    for c in s.bytes().filter(|&c| c != b'=') {
+        acc = ((acc << 6) | BASE64_CHARS.iter().position(|&b| b == c)? as u32) & 0xFFFF;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_range_of_references() {
+        assert_eq!(byte_range(&json!({ "memoryReference": "16", "count": 4 })), Ok((32, Some(4))));
+        assert_eq!(byte_range(&json!({ "memoryReference": "0x10", "offset": -3 })), Ok((29, None)));
+        assert_eq!(byte_range(&json!({ "memoryReference": "0x7fff", "offset": 1 })), Ok((0xFFFF, None)));
+
+        assert!(byte_range(&json!({ "count": 4 })).is_err());
+        assert!(byte_range(&json!({ "memoryReference": "x" })).is_err());
+        assert!(byte_range(&json!({ "memoryReference": "0", "offset": -1 })).is_err());
+        assert!(byte_range(&json!({ "memoryReference": "0x8000" })).is_err());
+        assert!(byte_range(&json!({ "memoryReference": "0xffffffffffffffff" })).is_err());
+        assert!(byte_range(&json!({ "memoryReference": "4", "offset": i64::MAX })).is_err());
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foo"), "Zm9v");
+        assert_eq!(decode_base64("Zm9vYg==").as_deref(), Some(&b"foob"[..]));
+        assert_eq!(decode_base64("Zm9v!"), None);
+
+        let data = (0..=255).collect::<Vec<u8>>();
+        for len in 0..data.len() {
+            assert_eq!(decode_base64(&encode_base64(&data[..len])).as_deref(), Some(&data[..len]));
+        }
+    }
+
+    #[test]
+    fn step_over_stops_after_the_call_returns() {
+        let over = RunMode::Over { pc: 0x12, sp: 3 };
+
+        // Entering the call and running inside it, even past the return address.
+        assert!(!over.finished(17, 3, 0x100, 4));
+        assert!(!over.finished(9, 4, 0x12, 4));
+        // Returning to the instruction after the call at the original depth.
+        assert!(over.finished(18, 4, 0x12, 3));
+        // The same address reached at a shallower depth is a different frame.
+        assert!(!over.finished(18, 3, 0x12, 2));
+    }
+
+    #[test]
+    fn step_out_stops_after_returning_from_the_frame() {
+        let out = RunMode::Out { sp: 3 };
+
+        // A nested call and its return don't finish the step.
+        assert!(!out.finished(17, 3, 0x100, 4));
+        assert!(!out.finished(18, 4, 0x13, 3));
+        assert!(!out.finished(9, 3, 0x15, 3));
+        assert!(out.finished(18, 3, 0x40, 2));
+
+        assert!(!RunMode::Continue.finished(18, 0, 0, 0));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use backend::{disassembler, Memory, SynacorVM};
+
+/// Changed words at most this far apart are shown as one run.
+const RUN_GAP: usize = 4;
+/// Symbols further than this before a run aren't worth naming.
+const SYMBOL_RANGE: usize = 0x100;
+const ROW_LEN: usize = 8;
+const DISASSEMBLY_LINES: usize = 4;
+
+/// Everything that differs between two VM states.
+#[derive(Debug)]
+pub struct StateDiff<'a> {
+    old: &'a SynacorVM,
+    new: &'a SynacorVM,
+    pub pc: Option<(u16, u16)>,
+    pub registers: Vec<(usize, u16, u16)>,
+    /// Stack entries from the bottom, missing on the side the stack is shorter.
+    pub stack: Vec<(usize, Option<u16>, Option<u16>)>,
+    /// Runs of changed memory, which may contain a few unchanged words.
+    pub runs: Vec<Range<usize>>,
+    pub changed_words: usize,
+}
+
+impl<'a> StateDiff<'a> {
+    pub fn between(old: &'a SynacorVM, new: &'a SynacorVM) -> Self {
+        let pc = (old.pc() != new.pc()).then_some((old.pc(), new.pc()));
+
+        let registers = old.registers().iter().zip(new.registers())
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, (&a, &b))| (i, a, b))
+            .collect();
+
+        let (old_stack, new_stack) = (old.stack().contents(), new.stack().contents());
+        let stack = (0..old_stack.len().max(new_stack.len()))
+            .map(|i| (i, old_stack.get(i).copied(), new_stack.get(i).copied()))
+            .filter(|(_, a, b)| a != b)
+            .collect();
+
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        let mut changed_words = 0;
+        for (addr, _) in old.memory().iter().zip(new.memory().iter()).enumerate().filter(|(_, (a, b))| a != b) {
+            changed_words += 1;
+            match runs.last_mut() {
+                Some(run) if addr - run.end <= RUN_GAP => run.end = addr + 1,
+                _ => runs.push(addr..addr + 1),
+            }
+        }
+
+        Self { old, new, pc, registers, stack, runs, changed_words }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pc.is_none() && self.registers.is_empty() && self.stack.is_empty() && self.runs.is_empty()
+    }
+
+    pub fn render(&self) -> Vec<String> {
+        if self.is_empty() { return vec!["No differences.".into()]; }
+
+        let mut lines = Vec::new();
+        if let Some((a, b)) = self.pc {
+            lines.push(format!("PC: {:04X} -> {:04X}", a, b));
+        }
+
+        if !self.registers.is_empty() {
+            let changes = self.registers.iter().map(|(i, a, b)| format!("({}) {:04X} -> {:04X}", i, a, b)).collect::<Vec<_>>();
+            lines.push(format!("Registers: {}", changes.join(", ")));
+        }
+
+        if !self.stack.is_empty() {
+            lines.push(format!("Stack: {} -> {} entries", self.old.stack().len(), self.new.stack().len()));
+            for (i, a, b) in &self.stack {
+                lines.push(format!("  [{}] {} -> {}", i, format_word(*a), format_word(*b)));
+            }
+        }
+
+        if self.runs.is_empty() { return lines; }
+
+        let symbols = symbols(self.old.memory());
+        lines.push(format!("Memory: {} word(s) in {} run(s)", self.changed_words, self.runs.len()));
+
+        for run in &self.runs {
+            let mut header = format!("  {:04X}..{:04X}", run.start, run.end - 1);
+            if let Some(symbol) = nearest_symbol(&symbols, run.start) { header.push_str(&format!("  {}", symbol)); }
+            lines.push(header);
+
+            let old = self.old.memory().read(run.clone()).collect::<Vec<_>>();
+            let new = self.new.memory().read(run.clone()).collect::<Vec<_>>();
+            for (i, (old, new)) in old.chunks(ROW_LEN).zip(new.chunks(ROW_LEN)).enumerate() {
+                lines.push(format!("    {:04X} old: {:04X?}", run.start + i * ROW_LEN, old));
+                lines.push(format!("         new: {:04X?}", new));
+            }
+
+            let mut addr = run.start;
+            for _ in 0..DISASSEMBLY_LINES {
+                if addr >= run.end { break; }
+
+                let (old, _) = disassembler::to_assembly_instruction(addr, self.old.memory());
+                let (new, len) = disassembler::to_assembly_instruction(addr, self.new.memory());
+                lines.push(format!("    {:04X}  {:<24} -> {}", addr, old.trim_end(), new.trim_end()));
+                addr += len;
+            }
+        }
+
+        lines
+    }
+}
+
+fn format_word(word: Option<u16>) -> String {
+    word.map_or("-".into(), |word| format!("{:04X}", word))
+}
+
+/// Guesses names for addresses by sweeping the memory for instructions:
+/// `call` targets become `sub_XXXX` and words read or written with
+/// `rmem`/`wmem` become `data_XXXX`.
+fn symbols(memory: &Memory) -> BTreeMap<usize, String> {
+    let mut symbols = BTreeMap::new();
+    let mut pc = 0;
+
+    while pc < memory.len() {
+        let (_, len) = disassembler::to_assembly_instruction(pc, memory);
+        let operand = |i: usize| (pc + i < memory.len()).then(|| memory[pc + i] as usize).filter(|&addr| addr < memory.len());
+
+        match memory[pc] {
+            15 => if let Some(addr) = operand(2) { symbols.entry(addr).or_insert_with(|| format!("data_{:04X}", addr)); },
+            16 => if let Some(addr) = operand(1) { symbols.entry(addr).or_insert_with(|| format!("data_{:04X}", addr)); },
+            17 => if let Some(addr) = operand(1) { symbols.insert(addr, format!("sub_{:04X}", addr)); },
+            _ => (),
+        }
+
+        pc += len;
+    }
+
+    symbols
+}
+
+fn nearest_symbol(symbols: &BTreeMap<usize, String>, addr: usize) -> Option<String> {
+    let (&start, name) = symbols.range(..=addr).next_back().filter(|(&start, _)| addr - start < SYMBOL_RANGE)?;
+    Some(if start == addr { name.clone() } else { format!("{}+{:X}", name, addr - start) })
+}
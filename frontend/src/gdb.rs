@@ -0,0 +1,443 @@
+//! GDB Remote Serial Protocol stub.
+//!
+//! Addresses and lengths in memory packets are counted in 16-bit words, and
+//! every word travels as two little-endian bytes. The register file is
+//! `r0`-`r7`, then `pc` and `sp` (the number of stack entries).
+
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use backend::{Error, Event, Fault};
+use crate::TerminalVM;
+
+const REGISTER_COUNT: usize = 10;
+const INTERRUPT_CHECK_INTERVAL: u64 = 0x10000;
+
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0">"#,
+    r#"<feature name="org.synacor.core">"#,
+    r#"<reg name="r0" bitsize="16" type="int"/><reg name="r1" bitsize="16" type="int"/>"#,
+    r#"<reg name="r2" bitsize="16" type="int"/><reg name="r3" bitsize="16" type="int"/>"#,
+    r#"<reg name="r4" bitsize="16" type="int"/><reg name="r5" bitsize="16" type="int"/>"#,
+    r#"<reg name="r6" bitsize="16" type="int"/><reg name="r7" bitsize="16" type="int"/>"#,
+    r#"<reg name="pc" bitsize="16" type="code_ptr"/><reg name="sp" bitsize="16" type="int"/>"#,
+    r#"</feature></target>"#,
+);
+
+pub fn serve(term: &mut TerminalVM, listener: TcpListener) -> io::Result<()> {
+    let (stream, addr) = listener.accept()?;
+    println!("GDB client connected from {}", addr);
+
+    GdbServer::new(term, stream).run()
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+pub struct GdbServer<'a> {
+    term: &'a mut TerminalVM,
+    stream: TcpStream,
+    pending: VecDeque<u8>,
+    breakpoints: HashSet<u16>,
+    output: Vec<u8>,
+    last_packet: String,
+    halted: bool,
+}
+
+impl<'a> GdbServer<'a> {
+    pub fn new(term: &'a mut TerminalVM, stream: TcpStream) -> Self {
+        Self {
+            term,
+            stream,
+            pending: VecDeque::new(),
+            breakpoints: HashSet::new(),
+            output: Vec::new(),
+            last_packet: String::new(),
+            halted: false,
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(incoming) = self.receive()? {
+            let packet = match incoming {
+                Incoming::Packet(packet) => packet,
+                Incoming::Interrupt => {
+                    self.send_packet("S02")?;
+                    continue;
+                }
+            };
+
+            match packet.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    self.send_packet("OK")?;
+                    return Ok(());
+                }
+                _ => {
+                    let reply = self.handle_packet(&packet)?;
+                    self.send_packet(&reply)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, packet: &str) -> io::Result<String> {
+        // Packets that don't start with an ASCII command letter are unsupported.
+        let (cmd, args) = match packet.split_at_checked(1) {
+            Some(split) => split,
+            None => return Ok(String::new()),
+        };
+
+        let reply = match cmd {
+            "?" => if self.halted { "W00".into() } else { "S05".into() },
+            "g" => (0..REGISTER_COUNT).map(|i| encode_word(self.read_register(i))).collect(),
+            "G" => match decode_words(args) {
+                Some(values) if values.len() == REGISTER_COUNT && values.iter().enumerate().all(|(i, &val)| self.valid_register(i, val)) => {
+                    for (i, &val) in values.iter().enumerate() {
+                        self.write_register(i, val);
+                    }
+                    "OK".into()
+                }
+                _ => "E01".into(),
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(i) if i < REGISTER_COUNT => encode_word(self.read_register(i)),
+                _ => "E01".into(),
+            }
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(i, val)| {
+                    Some((usize::from_str_radix(i, 16).ok()?, decode_words(val)?))
+                });
+
+                match parsed {
+                    Some((i, val)) if val.len() == 1 && self.valid_register(i, val[0]) => {
+                        self.write_register(i, val[0]);
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) if addr < 0x8000 => self.term.vm.memory().read(addr..addr.saturating_add(len).min(0x8000))
+                    .map(encode_word)
+                    .collect(),
+                _ => "E01".into(),
+            }
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, decode_words(data)?)));
+
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len && addr.checked_add(len).is_some_and(|end| end <= 0x8000) => {
+                        self.term.vm.memory_mut().write(addr, &data);
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "Z" | "z" => match parse_breakpoint(args) {
+                Some(addr) => {
+                    if cmd == "Z" {
+                        self.breakpoints.insert(addr);
+                    } else {
+                        self.breakpoints.remove(&addr);
+                    }
+                    "OK".into()
+                }
+                None => String::new(),
+            }
+            "s" | "c" => {
+                if let Some(addr) = u16::from_str_radix(args, 16).ok().filter(|&a| a < 0x8000) {
+                    self.move_pc(addr);
+                }
+
+                self.resume(cmd == "s")?
+            }
+            "H" => "OK".into(),
+            "q" => self.handle_query(args),
+            _ => String::new(),
+        };
+
+        Ok(reply)
+    }
+
+    fn handle_query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return "PacketSize=1000;qXfer:features:read+".into();
+        }
+
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            return match parse_range(range) {
+                Some((offset, _)) if offset >= TARGET_XML.len() => "l".into(),
+                Some((offset, len)) => {
+                    let end = offset.saturating_add(len).min(TARGET_XML.len());
+                    let prefix = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{}{}", prefix, &TARGET_XML[offset..end])
+                }
+                None => "E01".into(),
+            };
+        }
+
+        match query {
+            "Attached" => "1".into(),
+            "C" => "QC1".into(),
+            "fThreadInfo" => "m1".into(),
+            "sThreadInfo" => "l".into(),
+            _ => String::new(),
+        }
+    }
+
+    fn resume(&mut self, single_step: bool) -> io::Result<String> {
+        if self.halted {
+            return Ok("W00".into());
+        }
+
+        let mut steps = 0u64;
+
+        loop {
+            let pc = self.term.vm.pc();
+            if steps > 0 && self.breakpoints.contains(&pc) {
+                break;
+            }
+
+            self.term.pc_history.push(pc);
+
+            match self.term.vm.step() {
+                Err(fault) => {
+                    *self.term.vm.pc_mut() = fault.pc;
+                    self.flush_output()?;
+                    return Ok(format!("S{:02X}", fault_signal(&fault)));
+                }
+                Ok(Some(Event::Halt)) => {
+                    self.halted = true;
+                    self.flush_output()?;
+                    return Ok("W00".into());
+                }
+                Ok(Some(Event::Output(val))) => {
+                    self.output.push(val);
+                    self.term.response.push(val as char);
+                    if val == b'\n' { self.flush_output()?; }
+                }
+                Ok(Some(Event::Input(_))) => {
+                    self.flush_output()?;
+                    if self.term.input_queue.is_empty() { self.term.finish_response(); }
+
+                    if self.term.input_queue.is_empty() && !self.term.replay_line() && !self.read_input_line()? {
+                        break;
+                    }
+
+                    if let Some(Err(fault)) = self.term.consume_input() {
+                        *self.term.vm.pc_mut() = fault.pc;
+                        return Ok(format!("S{:02X}", fault_signal(&fault)));
+                    }
+                }
+                Ok(None) => {}
+            }
+
+            steps += 1;
+            if single_step { break; }
+
+            if steps.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && self.interrupted()? {
+                self.flush_output()?;
+                return Ok("S02".into());
+            }
+        }
+
+        self.flush_output()?;
+        Ok("S05".into())
+    }
+
+    fn read_input_line(&mut self) -> io::Result<bool> {
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(false);
+        }
+
+        self.term.write_input(input.trim());
+        Ok(true)
+    }
+
+    fn read_register(&self, i: usize) -> u16 {
+        match i {
+            0..=7 => self.term.vm.registers()[i],
+            8 => self.term.vm.pc(),
+            _ => self.term.vm.stack().pointer() as u16,
+        }
+    }
+
+    /// Whether `val` can be written to register `i` and still leave a VM that saves and loads.
+    fn valid_register(&self, i: usize, val: u16) -> bool {
+        match i {
+            0..=8 => val < 0x8000,
+            9 => self.term.vm.stack().limit().is_none_or(|limit| val as usize <= limit),
+            _ => false,
+        }
+    }
+
+    fn write_register(&mut self, i: usize, val: u16) {
+        match i {
+            0..=7 => self.term.vm.registers_mut()[i] = val,
+            8 => self.move_pc(val),
+            _ => {
+                let mut contents = self.term.vm.stack().contents().to_vec();
+                contents.resize(val as usize, 0);
+                self.term.vm.stack_mut().set_contents(contents);
+            }
+        }
+    }
+
+    /// Moves the PC, abandoning an `in` the VM was blocked on unless it stays put.
+    fn move_pc(&mut self, pc: u16) {
+        if pc != self.term.vm.pc() {
+            *self.term.vm.pc_mut() = pc;
+            self.term.vm.cancel_input();
+        }
+    }
+
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 64];
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(n) => {
+                self.pending.extend(&buf[..n]);
+
+                // The interrupt is answered by the caller's stop reply, so it mustn't be read again.
+                match self.pending.iter().position(|&b| b == 0x03) {
+                    Some(i) => {
+                        self.pending.remove(i);
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+
+        let packet = format!("O{}", encode_bytes(&self.output));
+        self.output.clear();
+        self.send_packet(&packet)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            let byte = match self.next_byte()? {
+                Some(b) => b,
+                None => return Ok(None),
+            };
+
+            match byte {
+                0x03 => return Ok(Some(Incoming::Interrupt)),
+                b'-' => {
+                    let packet = self.last_packet.clone();
+                    self.write_frame(&packet)?;
+                }
+                b'$' => {
+                    let mut data = Vec::new();
+                    loop {
+                        match self.next_byte()? {
+                            Some(b'#') => break,
+                            Some(b) => data.push(b),
+                            None => return Ok(None),
+                        }
+                    }
+
+                    let checksum = match (self.next_byte()?, self.next_byte()?) {
+                        (Some(hi), Some(lo)) => u8::from_str_radix(&String::from_utf8_lossy(&[hi, lo]), 16).ok(),
+                        _ => return Ok(None),
+                    };
+
+                    if checksum != Some(checksum_of(&data)) {
+                        self.stream.write_all(b"-")?;
+                        continue;
+                    }
+
+                    self.stream.write_all(b"+")?;
+                    return Ok(Some(Incoming::Packet(String::from_utf8_lossy(&data).into_owned())));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(b) = self.pending.pop_front() {
+            return Ok(Some(b));
+        }
+
+        let mut buf = [0; 1];
+        match self.stream.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        self.last_packet = data.into();
+        self.write_frame(data)
+    }
+
+    fn write_frame(&mut self, data: &str) -> io::Result<()> {
+        write!(self.stream, "${}#{:02x}", data, checksum_of(data.as_bytes()))?;
+        self.stream.flush()
+    }
+}
+
+fn fault_signal(fault: &Fault) -> u8 {
+    match fault.error {
+        Error::IllegalOpcode(_) => 4, // SIGILL
+        _ => 11, // SIGSEGV
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc: u8, &b| acc.wrapping_add(b))
+}
+
+fn encode_word(val: u16) -> String {
+    format!("{:02x}{:02x}", val & 0xFF, val >> 8)
+}
+
+fn encode_bytes(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_words(hex: &str) -> Option<Vec<u16>> {
+    if !hex.len().is_multiple_of(4) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(4).map(|i| {
+        let lo = u16::from_str_radix(hex.get(i..i + 2)?, 16).ok()?;
+        let hi = u16::from_str_radix(hex.get(i + 2..i + 4)?, 16).ok()?;
+        Some((hi << 8) | lo)
+    }).collect()
+}
+
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    Some((addr, len))
+}
+
+fn parse_breakpoint(s: &str) -> Option<u16> {
+    let mut parts = s.split(',');
+    if parts.next()? != "0" {
+        return None;
+    }
+
+    u16::from_str_radix(parts.next()?, 16).ok().filter(|&addr| addr < 0x8000)
+}
@@ -1,12 +1,34 @@
+pub mod tui;
+pub mod gdb;
+pub mod dap;
+pub mod map;
+pub mod walkthrough;
+pub mod replay;
+pub mod minimize;
+pub mod script;
+pub mod save;
+pub mod undo;
+pub mod timeline;
+pub mod diff;
+
 use std::collections::VecDeque;
-use std::{fs, mem, io, cmp, process};
+use std::path::{Path, PathBuf};
+use std::{fs, io, cmp, mem};
 use std::io::Write;
-use backend::{disassembler, Result, SynacorVM, Event};
-use backend::vm::STACK_LEN;
+use std::fmt::Display;
+use backend::{disassembler, parse_room, Fault, Result, SynacorVM, Event, Room, VmConfig};
 use colored::Colorize;
+use replay::{Recorder, Replay};
+use undo::UndoHistory;
+use timeline::Timeline;
 
-const SAVE_DATA_LEN: usize = 0x800A + STACK_LEN;
 const COMMAND_PREFIX: char = ':';
+const MEMORY_ROW_LEN: usize = 8;
+const FAULT_HISTORY_LEN: usize = 16;
+pub const UNDO_LIMIT: usize = 100;
+const NO_PROJECT: &str = "no project open, start with --project <dir>";
+/// Input lines after which a project gets an automatic timeline node.
+const AUTO_CHECKPOINT_LINES: usize = 20;
 
 #[derive(Debug)]
 pub struct TerminalVM {
@@ -14,9 +36,25 @@ pub struct TerminalVM {
     saved: bool,
     input_queue: VecDeque<u8>,
     last_command: Option<Vec<String>>,
-    save_state: Option<SynacorVM>,
+    save_state: Option<(SynacorVM, VecDeque<u8>)>,
+    undo: UndoHistory,
+    timeline: Option<Timeline>,
+    /// Input lines entered since the current timeline node.
+    timeline_inputs: Vec<String>,
+    recent_output: VecDeque<String>,
+    /// Codes found in the game's output so far.
+    codes: Vec<String>,
     pc_history: LimitedQueue<u16>,
     debug: bool,
+    fault: Option<Fault>,
+    response: String,
+    room: Option<Room>,
+    messages: Option<Vec<String>>,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    binary_hash: u64,
+    compress_saves: bool,
+    quit: bool,
 }
 
 impl TerminalVM {
@@ -27,19 +65,39 @@ impl TerminalVM {
             input_queue: VecDeque::new(),
             last_command: None,
             save_state: None,
+            undo: UndoHistory::new(UNDO_LIMIT),
+            timeline: None,
+            timeline_inputs: Vec::new(),
+            recent_output: VecDeque::new(),
+            codes: Vec::new(),
             pc_history: LimitedQueue::new(0x1000),
             debug: false,
+            fault: None,
+            response: String::new(),
+            room: None,
+            messages: None,
+            recorder: None,
+            replay: None,
+            binary_hash: 0,
+            compress_saves: true,
+            quit: false,
         }
     }
 
     pub fn load_state_buf(&mut self, buf: &[u8]) -> Result<(), &'static str> {
-        deserialize_vm(buf, &mut self.vm)?;
-        self.write_input("look");
+        let info = save::decode(buf, &mut self.vm)?;
+        self.undo.clear();
+        self.room = None;
+        self.binary_hash = info.binary_hash;
+        self.input_queue = info.pending_input.into();
+        self.recent_output = info.metadata.output.into();
+        self.codes = info.metadata.codes;
         Ok(())
     }
 
     pub fn load_binary(&mut self, bin: &[u16]) {
         self.vm.load_binary(bin);
+        self.binary_hash = save::binary_hash(bin);
     }
 
     pub fn run(&mut self, breakpoints: &[u16], output: &mut Option<impl Write>) -> Result<()> {
@@ -51,6 +109,12 @@ impl TerminalVM {
                 writeln!(out, "{:04X}    {}", self.vm.pc(), assembly).expect("could not write output");
             }
 
+            if self.replay.as_ref().is_some_and(|r| r.stops_at(self.vm.instructions())) {
+                println!();
+                self.stop_replay();
+                self.debug = true;
+            }
+
             if breakpoints.contains(&self.vm.pc()) {
                 self.debug = true;
                 println!();
@@ -58,15 +122,29 @@ impl TerminalVM {
             }
 
             if self.debug { self.show_debug(); }
+            if self.quit { break; }
 
-            let status = self.vm.step()?;
+            let status = match self.vm.step() {
+                Ok(status) => status,
+                Err(fault) => {
+                    self.enter_post_mortem(fault);
+                    continue;
+                }
+            };
+
+            self.fault = None;
             match status {
-                Some(Event::Halt) => break,
-                Some(Event::Output(val)) => print!("{}", val as char),
-                Some(Event::Input(dest)) => {
-                    while self.input_queue.is_empty() {
+                Some(Event::Halt) if self.undo.is_empty() || !self.await_rewind() => break,
+                Some(Event::Output(val)) => {
+                    print!("{}", val as char);
+                    self.response.push(val as char);
+                }
+                Some(Event::Input(_)) => {
+                    if self.input_queue.is_empty() { self.finish_response(); }
+
+                    while self.input_queue.is_empty() && !self.replay_line() {
                         let mut input = String::new();
-                        io::stdin().read_line(&mut input).unwrap();
+                        if io::stdin().read_line(&mut input).unwrap() == 0 { return Ok(()); }
                         input = input.trim().into();
 
                         if !input.starts_with(':') {
@@ -76,10 +154,16 @@ impl TerminalVM {
                         }
 
                         let words = split_words(input);
-                        self.handle_command(words).unwrap_or_else(print_err);
+                        self.run_command(words);
+                        if self.quit { return Ok(()); }
                     }
 
-                    self.vm.write_input(dest, self.input_queue.pop_front().unwrap())?;
+                    // A loaded save state may not be waiting for input.
+                    if self.vm.waiting().is_none() { continue; }
+
+                    if let Some(Err(fault)) = self.consume_input() {
+                        self.enter_post_mortem(fault);
+                    }
                 }
                 _ => {}
             }
@@ -90,11 +174,64 @@ impl TerminalVM {
 
     pub fn set_debug(&mut self, debug: bool) { self.debug = debug; }
 
-    fn handle_command(&mut self, mut words: Vec<String>) -> Result<(), &str> {
+    pub fn vm(&self) -> &SynacorVM { &self.vm }
+
+    /// The room parsed from the most recent game response that described one.
+    pub fn room(&self) -> Option<&Room> { self.room.as_ref() }
+
+    pub fn set_config(&mut self, config: VmConfig) { self.vm.set_config(config); }
+
+    pub fn set_compress_saves(&mut self, compress: bool) { self.compress_saves = compress; }
+
+    /// Keeps a save-state timeline in `dir`. A new project starts from the
+    /// current state and an existing one resumes from its current node.
+    pub fn open_project(&mut self, dir: &Path) -> Result<(), &'static str> {
+        let mut timeline = Timeline::open(dir)?;
+
+        match timeline.current() {
+            Some(id) => {
+                let state = timeline.checkout(id)?;
+                self.load_state_buf(&state)?;
+            }
+            None => { timeline.add(&self.encode_state()?, Vec::new(), None, self.vm.instructions())?; }
+        }
+
+        self.timeline = Some(timeline);
+        Ok(())
+    }
+
+    /// Number of commands `:undo` can go back, 0 to disable checkpoints.
+    pub fn set_undo_limit(&mut self, limit: usize) { self.undo.set_limit(limit); }
+
+    pub fn binary_hash(&self) -> u64 { self.binary_hash }
+
+    /// Logs every input line the VM consumes from now on.
+    pub fn record_to(&mut self, recorder: Recorder) { self.recorder = Some(recorder); }
+
+    /// Feeds recorded input lines whenever the VM asks for input.
+    pub fn set_replay(&mut self, replay: Replay) { self.replay = Some(replay); }
+
+    fn enter_post_mortem(&mut self, fault: Fault) {
+        *self.vm.pc_mut() = fault.pc;
+        self.debug = true;
+
+        self.notify("");
+        self.notify(format!("{} {}", "VM fault:".bold().red(), fault.to_string().red()));
+        self.notify("Debug mode enabled.".cyan());
+        self.fault = Some(fault);
+    }
+
+    fn run_command(&mut self, words: Vec<String>) {
+        if let Err(e) = self.handle_command(words) {
+            self.notify(format!("{} {}", "Error:".bold().red(), e.red()));
+        }
+    }
+
+    fn handle_command(&mut self, mut words: Vec<String>) -> Result<(), &'static str> {
         if words[0] == ":." {
-            match mem::replace(&mut self.last_command, None) {
+            match self.last_command.take() {
                 Some(prev_words) => {
-                    println!("{} {}", "Repeating".cyan(), prev_words.join(" ").bold().cyan());
+                    self.notify(format!("{} {}", "Repeating".cyan(), prev_words.join(" ").bold().cyan()));
                     words = prev_words;
                 }
                 None => return Err("no command to repeat"),
@@ -106,35 +243,118 @@ impl TerminalVM {
             "s" => { // save (file)
                 let filename = words.get(1).ok_or("no filename provided")?;
 
-                let buf = serialize_vm(&self.vm);
+                let buf = self.encode_state()?;
                 fs::write(filename, buf).map_err(|_| "could not write to file")?;
                 self.saved = true;
-                println!("{}", "VM state saved.".green());
+                self.notify("VM state saved.".green());
             }
             "l" => { // load (file)
                 let filename = words.get(1).ok_or("no filename provided")?;
-                let buf = fs::read(&filename).map_err(|_| "could not read file")?;
+                let buf = fs::read(filename).map_err(|_| "could not read file")?;
 
                 self.load_state_buf(&buf)?;
-                *self.vm.pc_mut() += 2;
-                print!("{}", "Save state loaded".green());
+                self.notify("Save state loaded".green());
             }
             "qs" => { // quick save
-                self.save_state = Some(self.vm.clone());
-                println!("{}", "State saved.".green());
+                self.save_state = Some((self.vm.clone(), self.input_queue.clone()));
+
+                if self.timeline.is_some() {
+                    let id = self.add_timeline_node()?;
+                    self.notify(format!("State saved as node {}.", id).green());
+                } else {
+                    self.notify("State saved.".green());
+                }
+            }
+            "saves" => { // list saves (dir)
+                let dir = words.get(1).map_or(PathBuf::from("."), PathBuf::from);
+                let saves = save::list(&dir)?;
+                if saves.is_empty() { return Err("no saves found"); }
+
+                for line in save::table(&saves, Some(self.binary_hash)) {
+                    self.notify(line);
+                }
+            }
+            "tree" => { // timeline tree
+                let lines = self.timeline.as_ref().ok_or(NO_PROJECT)?.render();
+                for line in lines {
+                    self.notify(line.yellow());
+                }
+            }
+            "goto" => { // go to timeline node (id or label)
+                let timeline = self.timeline.as_mut().ok_or(NO_PROJECT)?;
+                let id = timeline.find(words.get(1).ok_or("no node provided")?).ok_or("no such node")?;
+                let state = timeline.checkout(id)?;
+
+                self.load_state_buf(&state)?;
+                self.timeline_inputs.clear();
+                self.notify(format!("Moved to node {}.", id).green());
+            }
+            "branch" => { // label timeline node (name)
+                let label = words.get(1).ok_or("no name provided")?;
+                let current = self.timeline.as_ref().ok_or(NO_PROJECT)?.current();
+
+                // Label the current node unless the game has moved on from it.
+                let id = match current {
+                    Some(id) if self.timeline_inputs.is_empty() => id,
+                    _ => self.add_timeline_node()?,
+                };
+
+                self.timeline.as_mut().ok_or(NO_PROJECT)?.label(id, label)?;
+                self.notify(format!("Labelled node {} \"{}\".", id, label).green());
+            }
+            "diff" => { // compare with a saved state (qs, timeline node or file)
+                let slot = words.get(1).ok_or("no slot provided")?;
+                let saved = self.saved_state(slot)?;
+
+                for line in diff::StateDiff::between(&saved, &self.vm).render() {
+                    self.notify(line.yellow());
+                }
             }
             "ql" => { // quick load
-                self.vm = self.save_state.clone().ok_or("no save state available")?;
-                self.write_input("look");
-                print!("{}", "Save state loaded".green());
+                (self.vm, self.input_queue) = self.save_state.clone().ok_or("no save state available")?;
+                self.undo.clear();
+                self.notify("Save state loaded".green());
+            }
+            "undo" => { // undo (count)
+                let count = match words.get(1) {
+                    Some(s) => s.parse().map_err(|_| "invalid count")?,
+                    None => 1,
+                };
+
+                if self.undo.is_empty() { return Err("nothing to undo"); }
+
+                for _ in 0..count {
+                    let checkpoint = match self.undo.undo(self.vm.clone(), self.room.clone()) {
+                        Some(checkpoint) => checkpoint,
+                        None => break,
+                    };
+
+                    self.notify(format!("Undoing: {}", checkpoint.command).cyan());
+                    self.vm = checkpoint.vm;
+                    self.room = checkpoint.room;
+                    self.undo_timeline_input()?;
+                }
+
+                self.input_queue.clear();
+                self.saved = false;
+            }
+            "redo" => { // redo
+                let checkpoint = self.undo.redo(self.vm.clone(), self.room.clone()).ok_or("nothing to redo")?;
+
+                self.notify(format!("Redoing: {}", checkpoint.command).cyan());
+                if self.timeline.is_some() { self.timeline_inputs.push(checkpoint.command); }
+                self.vm = checkpoint.vm;
+                self.room = checkpoint.room;
+                self.input_queue.clear();
+                self.saved = false;
             }
             "d" => { // debug
                 if !self.debug {
                     self.debug = true;
-                    println!("{}", "Debug mode enabled.".cyan());
+                    self.notify("Debug mode enabled.".cyan());
                 } else {
                     self.debug = false;
-                    println!("{}", "Debug mode disabled.".yellow());
+                    self.notify("Debug mode disabled.".yellow());
                 }
             }
             "h" => { // (pc) history
@@ -143,16 +363,61 @@ impl TerminalVM {
 
                 let history = self.pc_history.contents();
 
-                println!("{}", "PC history:".yellow());
-                println!("{}", format!("{:04X?}", &history[history.len() - limit..]).yellow());
+                let line = format!("{:04X?}", &history[history.len() - limit..]);
+
+                self.notify("PC history:".yellow());
+                self.notify(line.yellow());
+            }
+            "x" => { // examine memory (address, length)
+                let addr = parse_hex(words.get(1), "no address provided")? as usize;
+                let len = match words.get(2) {
+                    Some(s) => s.parse().map_err(|_| "invalid length")?,
+                    None => MEMORY_ROW_LEN,
+                };
+
+                let memory = self.vm.memory();
+                let words = memory.read(addr..cmp::min(addr.saturating_add(len), memory.len())).collect::<Vec<_>>();
+                let rows = words
+                    .chunks(MEMORY_ROW_LEN)
+                    .enumerate()
+                    .map(|(i, row)| format!("{:04X}: {:04X?}", addr + i * MEMORY_ROW_LEN, row))
+                    .collect::<Vec<_>>();
+
+                for row in rows {
+                    self.notify(row.yellow());
+                }
             }
-            "q!" => process::exit(0), // quit (no confirm)
+            "w" => { // write memory (address, values...)
+                let addr = parse_hex(words.get(1), "no address provided")? as usize;
+                let values = words[2..].iter()
+                    .map(|s| parse_hex(Some(s), ""))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if values.is_empty() { return Err("no values provided"); }
+                if addr + values.len() > self.vm.memory().len() { return Err("address out of range"); }
+
+                self.vm.memory_mut().write(addr, &values);
+                self.saved = false;
+                self.notify(format!("Wrote {} word(s) at {:04X}.", values.len(), addr).green());
+            }
+            "r" => { // set register (register, value)
+                let reg = words.get(1).ok_or("no register provided")?.parse::<usize>().map_err(|_| "invalid register")?;
+                let val = parse_hex(words.get(2), "no value provided")?;
+                *self.vm.registers_mut().get_mut(reg).ok_or("invalid register")? = val;
+                self.saved = false;
+            }
+            "j" => { // jump (address)
+                *self.vm.pc_mut() = parse_hex(words.get(1), "no address provided")?;
+                self.vm.cancel_input();
+                self.saved = false;
+            }
+            "q!" => self.quit = true, // quit (no confirm)
             "q" => { // quit (force)
                 if !self.saved {
                     return Err("VM state has not been saved!");
                 }
 
-                process::exit(0);
+                self.quit = true;
             }
             _ => return Err("unknown command"),
         }
@@ -160,6 +425,22 @@ impl TerminalVM {
         Ok(())
     }
 
+    /// The VM stored in `slot`: the quick save, a timeline node or a save file.
+    fn saved_state(&self, slot: &str) -> Result<SynacorVM, &'static str> {
+        if slot == "qs" {
+            return self.save_state.as_ref().map(|(vm, _)| vm.clone()).ok_or("no save state available");
+        }
+
+        let buf = match self.timeline.as_ref().and_then(|timeline| Some((timeline, timeline.find(slot)?))) {
+            Some((timeline, id)) => timeline.state(id)?,
+            None => fs::read(slot).map_err(|_| "could not read file")?,
+        };
+
+        let mut vm = SynacorVM::new();
+        save::decode(&buf, &mut vm)?;
+        Ok(vm)
+    }
+
     fn show_debug(&mut self) {
         while self.debug {
             let (assembly, _) = disassembler::to_assembly_instruction(self.vm.pc() as usize, self.vm.memory());
@@ -169,8 +450,25 @@ impl TerminalVM {
             println!("{} {}", "Registers:".yellow().bold(), format!("{:04X?}", self.vm.registers()).yellow());
             println!("{} {}", "Stack:".yellow().bold(), format!("{:04X?}", self.vm.stack().contents()).yellow());
 
+            if !self.vm.partial_line().is_empty() || !self.input_queue.is_empty() {
+                let consumed = String::from_utf8_lossy(self.vm.partial_line());
+                let pending = String::from_utf8_lossy(self.input_queue.make_contiguous());
+                println!("{} {}", "Input:".yellow().bold(), format!("{:?} read, {:?} pending", consumed, pending).yellow());
+            }
+
+            if let Some(fault) = &self.fault {
+                let history = self.pc_history.contents();
+                let recent = &history[history.len().saturating_sub(FAULT_HISTORY_LEN)..];
+
+                println!("{} {}", "Fault:".red().bold(), fault.error.to_string().red());
+                println!("{} {}", "Recent PCs:".yellow().bold(), format!("{:04X?}", recent).yellow());
+            }
+
             let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
+            if io::stdin().read_line(&mut input).unwrap() == 0 {
+                self.quit = true;
+                return;
+            }
             input = input.trim().into();
 
             if input.is_empty() { return; }
@@ -181,10 +479,181 @@ impl TerminalVM {
             }
 
             let words = split_words(input);
-            self.handle_command(words).unwrap_or_else(print_err);
+            self.run_command(words);
+            if self.quit { return; }
+        }
+    }
+
+    /// Offers `:undo` once the game has halted, e.g. because the player died.
+    /// Returns whether a command restored a state to continue from.
+    fn await_rewind(&mut self) -> bool {
+        self.finish_response();
+        self.notify("Program halted. Use :undo to go back or :q! to quit.".cyan());
+
+        let halted = self.vm.state_hash();
+        while self.vm.state_hash() == halted {
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input).unwrap() == 0 { return false; }
+            input = input.trim().into();
+
+            if !input.starts_with(COMMAND_PREFIX) {
+                println!("{}", "Please type a command.".red());
+                continue;
+            }
+
+            self.run_command(split_words(input));
+            if self.quit { return false; }
+        }
+
+        true
+    }
+
+    fn notify(&mut self, msg: impl Display) {
+        match &mut self.messages {
+            Some(messages) => messages.push(msg.to_string()),
+            None => println!("{}", msg),
         }
     }
 
+    fn finish_response(&mut self) {
+        if let Some(room) = parse_room(&self.response) {
+            self.room = Some(room);
+        }
+
+        for code in walkthrough::find_codes(&self.response) {
+            if !self.codes.contains(&code) { self.codes.push(code); }
+        }
+
+        for line in self.response.lines().filter(|line| !line.trim().is_empty()) {
+            if self.recent_output.len() == save::RECENT_OUTPUT_LINES { self.recent_output.pop_front(); }
+            self.recent_output.push_back(line.into());
+        }
+
+        self.response.clear();
+    }
+
+    /// Completes an `in` instruction with the next queued byte, topping the queue
+    /// up from the replay if there is one. Returns `None` if no input is available.
+    fn consume_input(&mut self) -> Option<Result<(), Fault>> {
+        if self.input_queue.is_empty() { self.replay_line(); }
+
+        let val = self.input_queue.pop_front()?;
+        if self.vm.partial_line().is_empty() {
+            self.undo.line_started(&self.vm, self.room.as_ref());
+
+            if self.timeline.is_some() && self.timeline_inputs.len() >= AUTO_CHECKPOINT_LINES {
+                if let Err(e) = self.add_timeline_node() {
+                    self.notify(format!("{} {}", "Error:".bold().red(), e.red()));
+                }
+            }
+        }
+        let line = (val == b'\n').then(|| String::from_utf8_lossy(self.vm.partial_line()).into_owned());
+
+        if let Err(fault) = self.vm.write_input(val) {
+            self.input_queue.push_front(val);
+            return Some(Err(fault));
+        }
+
+        if let Some(line) = line {
+            if self.timeline.is_some() { self.timeline_inputs.push(line.clone()); }
+            self.undo.line_finished(line);
+        }
+
+        let instructions = self.vm.instructions();
+        if let Some(recorder) = &mut self.recorder {
+            if recorder.record(val, instructions).is_err() {
+                self.recorder = None;
+                self.notify(format!("{} {}", "Error:".bold().red(), "could not write to replay file, recording stopped".red()));
+            }
+        }
+
+        Some(Ok(()))
+    }
+
+    /// Queues the next replayed line, returning whether there was one.
+    fn replay_line(&mut self) -> bool {
+        let instructions = self.vm.instructions();
+        let replay = match &mut self.replay {
+            Some(replay) => replay,
+            None => return false,
+        };
+
+        if replay.stops_before_next_line(instructions) {
+            if replay.stops_at(instructions) { self.debug = true; }
+            self.stop_replay();
+            return false;
+        }
+
+        let (expected, line) = match replay.next_line() {
+            Some(next) => next,
+            None => {
+                self.replay = None;
+                self.notify("Replay finished.".green());
+                return false;
+            }
+        };
+
+        if let Some(expected) = expected.filter(|&expected| expected != instructions) {
+            let line_number = replay.line();
+            self.notify(format!("Replay diverged at line {}: recorded at instruction {}, now at {}.", line_number, expected, instructions).yellow());
+        }
+
+        self.notify(format!("> {}", line).cyan());
+        self.write_input(&line);
+        true
+    }
+
+    fn stop_replay(&mut self) {
+        if let Some(replay) = self.replay.take() {
+            self.notify(format!("Replay stopped after {} line(s) at instruction {}.", replay.line(), self.vm.instructions()).cyan());
+        }
+    }
+
+    /// Drops the last input entered since the current timeline node. With none
+    /// left, the state is from before that node, so the parent becomes current
+    /// and the inputs that led to the node become unsaved inputs again.
+    fn undo_timeline_input(&mut self) -> Result<(), &'static str> {
+        while self.timeline_inputs.is_empty() {
+            let timeline = match self.timeline.as_mut() {
+                Some(timeline) => timeline,
+                None => break,
+            };
+
+            let node = match timeline.current().and_then(|id| timeline.node(id)) {
+                Some(node) => node.clone(),
+                None => break,
+            };
+
+            timeline.set_current(node.parent)?;
+            self.timeline_inputs = node.inputs;
+        }
+
+        self.timeline_inputs.pop();
+        Ok(())
+    }
+
+    /// Saves the current state as a child of the current timeline node.
+    fn add_timeline_node(&mut self) -> Result<usize, &'static str> {
+        let state = self.encode_state()?;
+        let room = self.room.as_ref().map(|room| room.name.clone());
+        let instructions = self.vm.instructions();
+
+        let timeline = self.timeline.as_mut().ok_or(NO_PROJECT)?;
+        timeline.add(&state, mem::take(&mut self.timeline_inputs), room, instructions)
+    }
+
+    /// Encodes the current state along with any input the game hasn't read yet.
+    fn encode_state(&self) -> Result<Vec<u8>, &'static str> {
+        let pending = self.input_queue.iter().copied().collect::<Vec<_>>();
+        let metadata = save::Metadata {
+            room: self.room.as_ref().map(|room| room.name.clone()),
+            output: self.recent_output.iter().cloned().collect(),
+            codes: self.codes.clone(),
+        };
+
+        save::encode(&self.vm, &pending, &metadata, self.binary_hash, self.compress_saves)
+    }
+
     fn write_input(&mut self, input: &str) {
         for b in input.bytes() {
             self.input_queue.push_back(b);
@@ -194,6 +663,12 @@ impl TerminalVM {
     }
 }
 
+impl Default for TerminalVM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LimitedQueue<T> {
     contents: Vec<T>,
@@ -223,6 +698,8 @@ impl<T> LimitedQueue<T> {
     pub fn peek_last(&self) -> Option<&T> { self.contents.last() }
 
     pub fn len(&self) -> usize { self.contents.len() }
+
+    pub fn is_empty(&self) -> bool { self.contents.is_empty() }
 }
 
 pub fn to_u8_vec(src: &[u16]) -> Vec<u8> {
@@ -252,31 +729,7 @@ fn split_words(s: String) -> Vec<String> {
     s.split_whitespace().map(|s| s.into()).collect()
 }
 
-fn serialize_vm(vm: &SynacorVM) -> Vec<u8> {
-    let mut data = [0; SAVE_DATA_LEN];
-
-    data[0] = vm.pc();
-    data[0x1..0x9].clone_from_slice(vm.registers());
-    data[0x9..0x8009].clone_from_slice(vm.memory());
-    data[0x8009] = vm.stack().pointer() as u16;
-    data[0x800A..SAVE_DATA_LEN].clone_from_slice(vm.stack().full_contents());
-    to_u8_vec(&data)
-}
-
-fn deserialize_vm(buf: &[u8], vm: &mut SynacorVM) -> Result<(), &'static str> {
-    if buf.len() < SAVE_DATA_LEN * 2 {
-        return Err("Invalid data length");
-    }
-
-    let data = to_u16_vec(buf);
-    *vm.pc_mut() = data[0];
-    vm.registers_mut().clone_from_slice(&data[0x1..0x9]);
-    vm.memory_mut().clone_from_slice(&data[0x9..0x8009]);
-    *vm.stack_mut().pointer_mut() = data[0x8009] as usize;
-    vm.stack_mut().full_contents_mut().clone_from_slice(&data[0x800A..SAVE_DATA_LEN]);
-    Ok(())
+fn parse_hex(word: Option<&String>, missing: &'static str) -> Result<u16, &'static str> {
+    let val = u16::from_str_radix(word.ok_or(missing)?, 16).map_err(|_| "invalid hex value")?;
+    if val < 0x8000 { Ok(val) } else { Err("value out of range") }
 }
-
-fn print_err(e: &str) {
-    println!("{} {}", "Error:".bold().red(), e.red());
-}
\ No newline at end of file
@@ -3,23 +3,128 @@ use clap::Parser;
 use colored::Colorize;
 use std::fs;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::ExitCode;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 use frontend::TerminalVM;
+use frontend::minimize::{Minimizer, Predicate};
+use frontend::replay::{Recorder, Replay};
+use frontend::diff::StateDiff;
+use frontend::save;
+use frontend::script::Script;
+use frontend::walkthrough::Walkthrough;
+use backend::{Exit, GameSession, SynacorVM, VmConfig};
+use backend::io::{ReaderSource, StdoutSink};
+
+const EXIT_INPUT_EXHAUSTED: u8 = 2;
+const EXIT_FAULT: u8 = 3;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// Binary to execute
-    filename: PathBuf,
+    filename: Option<PathBuf>,
 
     /// Load the provided file as VM state
     #[clap(short, long)]
     load_state: bool,
 
+    /// With --load-state, treat the file as the binary and load its most
+    /// recent save from the current directory
+    #[clap(long, requires = "load-state")]
+    latest: bool,
+
+    /// List the saves in the given directory
+    #[clap(long)]
+    saves: Option<PathBuf>,
+
+    /// Show the memory, registers and stack that differ between two saves
+    #[clap(long, number_of_values = 2, value_names = &["A", "B"])]
+    diff: Vec<PathBuf>,
+
     /// Start in debug mode
     #[clap(short, long)]
     debug: bool,
 
+    /// Run inside the full-screen debugger
+    #[clap(long)]
+    tui: bool,
+
+    /// Serve the GDB remote protocol on the given local port
+    #[clap(long)]
+    gdb: Option<u16>,
+
+    /// Serve the Debug Adapter Protocol over stdio
+    #[clap(long)]
+    dap: bool,
+
+    /// Recover from out-of-spec behaviour instead of faulting
+    #[clap(long)]
+    lenient: bool,
+
+    /// Maximum number of stack entries (unbounded by default)
+    #[clap(long)]
+    stack_limit: Option<usize>,
+
+    /// Explore the game world and write the map as DOT and JSON
+    #[clap(long)]
+    map: bool,
+
+    /// Play the game unattended following the given walkthrough file
+    #[clap(long)]
+    walkthrough: Option<PathBuf>,
+
+    /// Record every input line to the given file
+    #[clap(long)]
+    record: Option<PathBuf>,
+
+    /// Feed input recorded with --record back to the VM
+    #[clap(long)]
+    replay: Option<PathBuf>,
+
+    /// Stop replaying before the given line of the recording
+    #[clap(long, requires = "replay")]
+    stop_at_line: Option<usize>,
+
+    /// Stop replaying once the VM has executed the given number of instructions
+    #[clap(long, requires = "replay")]
+    stop_at_instruction: Option<u64>,
+
+    /// Shrink the given recording to the fewest lines that satisfy --predicate
+    #[clap(long, requires = "predicate")]
+    minimize: Option<PathBuf>,
+
+    /// Condition for --minimize: output:<text>, room:<name>, mem:<addr>=<value> or fault
+    #[clap(long)]
+    predicate: Option<String>,
+
+    /// Write save states without compression
+    #[clap(long)]
+    no_compress: bool,
+
+    /// Number of commands that can be undone with :undo, 0 to disable
+    #[clap(long, default_value_t = frontend::UNDO_LIMIT)]
+    undo_limit: usize,
+
+    /// Keep a branching timeline of save states in the given project directory
+    #[clap(long)]
+    project: Option<PathBuf>,
+
+    /// Run a regression test script against the game
+    #[clap(long)]
+    script: Option<PathBuf>,
+
+    /// Run without the debugger, reading commands from stdin (or --input) and
+    /// printing only game output. Exits with 0 on halt, 2 when the input runs
+    /// out and 3 on a VM fault
+    #[clap(long)]
+    batch: bool,
+
+    /// Read commands for --batch from the given file
+    #[clap(long, requires = "batch")]
+    input: Option<PathBuf>,
+
     /// Debug breakpoints
     #[clap(short, long)]
     breakpoints: Vec<String>,
@@ -33,23 +138,78 @@ struct Args {
     output: Option<PathBuf>,
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args: Args = Args::parse();
 
     run(args).unwrap_or_else(|e| {
         eprintln!("{} {}", "Error:".red().bold(), e.to_string().red());
-    });
+        ExitCode::FAILURE
+    })
 }
 
-fn run(args: Args) -> Result<(), Box<dyn Error>> {
-    let buf = fs::read(&args.filename)?;
+fn run(args: Args) -> Result<ExitCode, Box<dyn Error>> {
+    if args.batch {
+        colored::control::set_override(false);
+    }
+
+    if args.dap {
+        let mut vm = TerminalVM::new();
+        vm.set_config(vm_config(&args));
+        if let Some(filename) = &args.filename {
+            let buf = fs::read(filename)?;
+            if args.load_state {
+                vm.load_state_buf(&buf)?;
+            } else {
+                vm.load_binary(&frontend::to_u16_vec(&buf));
+            }
+        }
+
+        frontend::dap::serve(&mut vm)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(dir) = &args.saves {
+        let saves = save::list(dir)?;
+        if saves.is_empty() {
+            println!("No saves found.");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        // Mark saves from other binaries only if we know which binary to compare with.
+        let binary_hash = match &args.filename {
+            Some(path) if !args.load_state => Some(save::binary_hash(&frontend::to_u16_vec(&fs::read(path)?))),
+            _ => None,
+        };
+
+        for line in save::table(&saves, binary_hash) {
+            println!("{}", line);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let [old, new] = &args.diff[..] {
+        let load = |path: &PathBuf| -> Result<SynacorVM, Box<dyn Error>> {
+            let mut vm = SynacorVM::new();
+            save::decode(&fs::read(path)?, &mut vm)?;
+            Ok(vm)
+        };
+
+        let (old, new) = (load(old)?, load(new)?);
+        for line in StateDiff::between(&old, &new).render() {
+            println!("{}", line);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let filename = args.filename.clone().ok_or("no binary provided")?;
+    let buf = fs::read(&filename)?;
     let bin = frontend::to_u16_vec(&buf);
 
     if args.disassemble {
         println!("Disassembling...");
 
         let path = args.output.unwrap_or_else(|| {
-            let mut p = args.filename;
+            let mut p = filename;
             p.set_extension("s");
             p
         });
@@ -57,18 +217,92 @@ fn run(args: Args) -> Result<(), Box<dyn Error>> {
         backend::disassemble(&bin, &mut file)?;
 
         println!("Done.");
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
     let mut vm = TerminalVM::new();
+    vm.set_config(vm_config(&args));
+    vm.set_compress_saves(!args.no_compress);
 
-    if args.load_state {
+    if args.load_state && args.latest {
+        let path = save::latest(Path::new("."), save::binary_hash(&bin))?.ok_or("no saves found for this binary")?;
+        vm.load_state_buf(&fs::read(&path)?)?;
+        if !args.batch { println!("{}", format!("Loaded {}", path.display()).green()); }
+    } else if args.load_state {
         vm.load_state_buf(&buf)?;
-        print!("{}", "VM state loaded".green());
+        if !args.batch { println!("{}", "VM state loaded".green()); }
     } else {
         vm.load_binary(&bin);
     }
 
+    if args.map {
+        println!("Mapping...");
+
+        let map = frontend::map::explore(GameSession::new(vm.vm().clone()))?;
+        let path = args.output.unwrap_or_else(|| filename.with_extension("dot"));
+        fs::write(path.with_extension("dot"), map.to_dot())?;
+        fs::write(path.with_extension("json"), map.to_json())?;
+
+        println!("Mapped {} rooms.", map.rooms.len());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(path) = &args.walkthrough {
+        let walkthrough = Walkthrough::parse(&fs::read_to_string(path)?)?;
+        let mut session = GameSession::new(vm.vm().clone());
+        let codes = walkthrough.run(&mut session)?;
+
+        println!("Walkthrough complete, found {} codes.", codes.len());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let (Some(path), Some(predicate)) = (&args.minimize, &args.predicate) {
+        let predicate = Predicate::parse(predicate)?;
+        let lines = Replay::parse(&fs::read_to_string(path)?).into_lines();
+        let start = GameSession::new(vm.vm().clone());
+
+        let mut minimizer = Minimizer::new(&start, &lines, &predicate);
+        let minimal = minimizer.minimize()?;
+        let mut out = minimal.join("\n");
+        out.push('\n');
+
+        match &args.output {
+            Some(path) => fs::write(path, out)?,
+            None => print!("{}", out),
+        }
+
+        eprintln!("Reduced {} lines to {} in {} runs.", lines.len(), minimal.len(), minimizer.runs());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(path) = &args.script {
+        let script = Script::parse(&fs::read_to_string(path)?)?;
+        let report = script.run(&mut GameSession::new(vm.vm().clone()), vm.binary_hash());
+
+        println!("{} passed, {} failed", report.passed, report.failed);
+        return Ok(if report.failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
+
+    if args.batch {
+        let reader: Box<dyn BufRead> = match &args.input {
+            Some(path) => Box::new(BufReader::new(File::open(path)?)),
+            None => Box::new(io::stdin().lock()),
+        };
+
+        let mut vm = vm.vm().clone();
+        let code = match vm.run_with_io(&mut ReaderSource::new(reader), &mut StdoutSink) {
+            Ok(Exit::Halted) => ExitCode::SUCCESS,
+            Ok(Exit::InputExhausted) => ExitCode::from(EXIT_INPUT_EXHAUSTED),
+            Err(fault) => {
+                io::stdout().flush()?;
+                eprintln!("VM fault: {}", fault);
+                ExitCode::from(EXIT_FAULT)
+            }
+        };
+
+        return Ok(code);
+    }
+
     let breakpoints = args.breakpoints.iter()
         .map(|s| u16::from_str_radix(s, 16))
         .collect::<Result<Vec<_>, _>>()?;
@@ -76,6 +310,38 @@ fn run(args: Args) -> Result<(), Box<dyn Error>> {
     let mut output_file = args.output.map(|path| File::create(path).unwrap());
 
     vm.set_debug(args.debug);
-    vm.run(&breakpoints, &mut output_file)?;
-    Ok(())
+    vm.set_undo_limit(args.undo_limit);
+
+    if let Some(dir) = &args.project {
+        vm.open_project(dir)?;
+    }
+
+    if let Some(path) = &args.record {
+        vm.record_to(Recorder::new(File::create(path)?));
+    }
+
+    if let Some(path) = &args.replay {
+        let mut replay = Replay::parse(&fs::read_to_string(path)?);
+        replay.set_stop_line(args.stop_at_line);
+        replay.set_stop_instruction(args.stop_at_instruction);
+        vm.set_replay(replay);
+    }
+
+    if let Some(port) = args.gdb {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Waiting for a GDB client on port {}...", port);
+        frontend::gdb::serve(&mut vm, listener)?;
+    } else if args.tui {
+        frontend::tui::run(&mut vm, &breakpoints)?;
+    } else {
+        vm.run(&breakpoints, &mut output_file)?;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn vm_config(args: &Args) -> VmConfig {
+    let mut config = if args.lenient { VmConfig::lenient() } else { VmConfig::strict() };
+    if args.stack_limit.is_some() { config.stack_limit = args.stack_limit; }
+    config
 }
@@ -0,0 +1,167 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::ops::Range;
+use backend::{parse_room, GameSession, Room, SessionState, STEP_LIMIT};
+use serde_json::json;
+
+pub const MAX_ROOMS: usize = 1000;
+const PROBE_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct MapExit {
+    pub from: usize,
+    pub to: usize,
+    pub direction: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WorldMap {
+    pub rooms: Vec<Room>,
+    pub exits: Vec<MapExit>,
+    /// Exits that ended the game, with the last thing it printed.
+    pub deaths: Vec<(usize, String, String)>,
+}
+
+/// Explores every exit breadth-first, cloning the session at each room.
+///
+/// Rooms are told apart by name, description and a hash of memory. Words that
+/// change without the player moving, such as the input buffer, are left out of
+/// the hash so that revisiting a room maps onto the same node.
+pub fn explore(mut start: GameSession) -> Result<WorldMap, &'static str> {
+    start.set_step_limit(Some(STEP_LIMIT));
+
+    let output = start.send("look");
+    let room = parse_room(&output).ok_or("could not find a room description at the start")?;
+    let volatile = volatile_ranges(&start);
+
+    let mut map = WorldMap::default();
+    let mut ids = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    ids.insert(room_key(&room, &start, &volatile), 0);
+    map.rooms.push(room);
+    queue.push_back((0, start));
+
+    while let Some((id, session)) = queue.pop_front() {
+        let exits = map.rooms[id].exits.clone();
+
+        for direction in exits {
+            let mut next = session.clone();
+            let output = next.send(&format!("go {}", direction));
+
+            if *next.state() != SessionState::WaitingForInput {
+                let last_line = output.trim().lines().last().unwrap_or_default().to_string();
+                map.deaths.push((id, direction, last_line));
+                continue;
+            }
+
+            let room = match parse_room(&output) {
+                Some(room) => room,
+                None => continue,
+            };
+
+            let key = room_key(&room, &next, &volatile);
+            let to = match ids.get(&key) {
+                Some(&to) => to,
+                None if map.rooms.len() >= MAX_ROOMS => continue,
+                None => {
+                    let to = map.rooms.len();
+                    ids.insert(key, to);
+                    map.rooms.push(room);
+                    queue.push_back((to, next));
+                    to
+                }
+            };
+
+            map.exits.push(MapExit { from: id, to, direction });
+        }
+    }
+
+    Ok(map)
+}
+
+impl WorldMap {
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph world {\n    node [shape=box];\n");
+
+        for (id, room) in self.rooms.iter().enumerate() {
+            let mut label = room.name.clone();
+            if !room.items.is_empty() {
+                label.push_str(&format!("\n({})", room.items.join(", ")));
+            }
+
+            out.push_str(&format!("    r{} [label={:?}];\n", id, label));
+        }
+
+        for exit in &self.exits {
+            out.push_str(&format!("    r{} -> r{} [label={:?}];\n", exit.from, exit.to, exit.direction));
+        }
+
+        for (i, (from, direction, text)) in self.deaths.iter().enumerate() {
+            out.push_str(&format!("    d{} [label={:?}, shape=octagon, color=red];\n", i, text));
+            out.push_str(&format!("    r{} -> d{} [label={:?}, color=red];\n", from, i, direction));
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let rooms = self.rooms.iter().enumerate().map(|(id, room)| json!({
+            "id": id,
+            "name": room.name,
+            "description": room.description,
+            "items": room.items,
+        })).collect::<Vec<_>>();
+
+        let exits = self.exits.iter().map(|exit| json!({
+            "from": exit.from,
+            "to": exit.to,
+            "direction": exit.direction,
+        })).collect::<Vec<_>>();
+
+        let deaths = self.deaths.iter().map(|(from, direction, text)| json!({
+            "from": from,
+            "direction": direction,
+            "output": text,
+        })).collect::<Vec<_>>();
+
+        let json = json!({ "rooms": rooms, "exits": exits, "deaths": deaths });
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+}
+
+fn room_key(room: &Room, session: &GameSession, volatile: &[Range<usize>]) -> (String, String, u64) {
+    (room.name.clone(), room.description.clone(), session.vm().memory().hash(volatile))
+}
+
+/// Finds memory ranges that change when the player runs commands without moving.
+fn volatile_ranges(start: &GameSession) -> Vec<Range<usize>> {
+    // A long unknown command dirties the whole input buffer, not just the
+    // part a short command like "look" happens to overwrite.
+    let probes = ["look".to_string(), "inv".to_string(), "x".repeat(PROBE_LEN), "look".to_string()];
+
+    let mut probed = start.clone();
+    let mut volatile = BTreeSet::new();
+
+    for command in &probes {
+        probed.send(command);
+
+        let changed = start.vm().memory().iter()
+            .zip(probed.vm().memory().iter())
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(addr, _)| addr);
+
+        volatile.extend(changed);
+    }
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for addr in volatile {
+        match ranges.last_mut() {
+            Some(range) if range.end == addr => range.end += 1,
+            _ => ranges.push(addr..addr + 1),
+        }
+    }
+
+    ranges
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use backend::{parse_room, GameSession, SessionState, STEP_LIMIT};
+use crate::parse_hex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    OutputContains(String),
+    Room(String),
+    Memory(usize, u16),
+    Faults,
+}
+
+impl Predicate {
+    /// Parses `output:<text>`, `room:<name>`, `mem:<addr>=<value>` (hex) or `fault`.
+    pub fn parse(s: &str) -> Result<Self, &'static str> {
+        let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+
+        match kind {
+            "output" => Ok(Predicate::OutputContains(arg.into())),
+            "room" => Ok(Predicate::Room(arg.into())),
+            "mem" => {
+                let (addr, val) = arg.split_once('=').ok_or("expected mem:<addr>=<value>")?;
+                let addr = parse_hex(Some(&addr.into()), "no address provided")? as usize;
+                let val = u16::from_str_radix(val, 16).map_err(|_| "invalid hex value")?;
+                Ok(Predicate::Memory(addr, val))
+            }
+            "fault" => Ok(Predicate::Faults),
+            _ => Err("unknown predicate, expected output:, room:, mem: or fault"),
+        }
+    }
+
+    fn holds(&self, session: &GameSession, output: &str, room: Option<&str>) -> bool {
+        match self {
+            Predicate::OutputContains(text) => output.contains(text.as_str()),
+            Predicate::Room(name) => room == Some(name.as_str()),
+            Predicate::Memory(addr, val) => session.vm().memory()[*addr] == *val,
+            Predicate::Faults => session.fault().is_some(),
+        }
+    }
+}
+
+/// Reduces `lines` to a 1-minimal subset that still makes `predicate` hold at
+/// some point while replaying it from `start`, using delta debugging.
+pub struct Minimizer<'a> {
+    start: &'a GameSession,
+    lines: &'a [String],
+    predicate: &'a Predicate,
+    cache: HashMap<Vec<usize>, bool>,
+}
+
+impl<'a> Minimizer<'a> {
+    pub fn new(start: &'a GameSession, lines: &'a [String], predicate: &'a Predicate) -> Self {
+        Self { start, lines, predicate, cache: HashMap::new() }
+    }
+
+    pub fn minimize(&mut self) -> Result<Vec<String>, &'static str> {
+        let mut current = (0..self.lines.len()).collect::<Vec<_>>();
+
+        if !self.test(&current) {
+            return Err("the full replay does not satisfy the predicate");
+        }
+
+        if self.test(&[]) {
+            return Ok(Vec::new());
+        }
+
+        let mut n = 2;
+        while current.len() >= 2 {
+            let chunk_len = current.len().div_ceil(n);
+            let chunks = current.chunks(chunk_len).map(<[usize]>::to_vec).collect::<Vec<_>>();
+
+            let reduced = chunks.iter().find(|chunk| self.test(chunk)).cloned();
+            if let Some(chunk) = reduced {
+                current = chunk;
+                n = 2;
+                continue;
+            }
+
+            let complements = (0..chunks.len())
+                .map(|i| [&chunks[..i], &chunks[i + 1..]].concat().concat())
+                .collect::<Vec<_>>();
+
+            let reduced = complements.into_iter().find(|complement| self.test(complement));
+            if let Some(complement) = reduced {
+                current = complement;
+                n = (n - 1).max(2);
+                continue;
+            }
+
+            if n >= current.len() { break; }
+            n = (n * 2).min(current.len());
+        }
+
+        Ok(current.into_iter().map(|i| self.lines[i].clone()).collect())
+    }
+
+    /// Number of distinct candidates replayed so far.
+    pub fn runs(&self) -> usize { self.cache.len() }
+
+    fn test(&mut self, indices: &[usize]) -> bool {
+        if let Some(&result) = self.cache.get(indices) {
+            return result;
+        }
+
+        let result = self.replay(indices);
+        self.cache.insert(indices.to_vec(), result);
+        result
+    }
+
+    fn replay(&self, indices: &[usize]) -> bool {
+        let mut session = self.start.clone();
+        session.set_step_limit(Some(STEP_LIMIT));
+
+        let mut output = session.run();
+        let mut room = parse_room(&output).map(|r| r.name);
+
+        for &i in indices {
+            if self.predicate.holds(&session, &output, room.as_deref()) {
+                return true;
+            }
+
+            if *session.state() != SessionState::WaitingForInput {
+                return false;
+            }
+
+            let response = session.send(&self.lines[i]);
+            if let Some(r) = parse_room(&response) {
+                room = Some(r.name);
+            }
+            output.push_str(&response);
+        }
+
+        self.predicate.holds(&session, &output, room.as_deref())
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+/// Writes every consumed input line to a file as `<instruction count>\t<line>`.
+#[derive(Debug)]
+pub struct Recorder {
+    file: File,
+    line: Vec<u8>,
+    start: Option<u64>,
+}
+
+impl Recorder {
+    pub fn new(file: File) -> Self {
+        Self { file, line: Vec::new(), start: None }
+    }
+
+    /// Records one byte read by `in`, at the given instruction count.
+    pub fn record(&mut self, val: u8, instructions: u64) -> io::Result<()> {
+        let start = *self.start.get_or_insert(instructions);
+
+        if val != b'\n' {
+            self.line.push(val);
+            return Ok(());
+        }
+
+        writeln!(self.file, "{}\t{}", start, String::from_utf8_lossy(&self.line))?;
+        self.line.clear();
+        self.start = None;
+        Ok(())
+    }
+}
+
+/// Input lines read back from a recording, in the order they were consumed.
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    lines: VecDeque<(Option<u64>, String)>,
+    line: usize,
+    stop_line: Option<usize>,
+    stop_instruction: Option<u64>,
+}
+
+impl Replay {
+    /// Parses a recording. Lines without an instruction count are plain commands
+    /// and are fed without checking for divergence.
+    pub fn parse(source: &str) -> Self {
+        let lines = source.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| match line.split_once('\t') {
+                Some((count, text)) if count.parse::<u64>().is_ok() => (count.parse().ok(), text.to_string()),
+                _ => (None, line.to_string()),
+            })
+            .collect();
+
+        Self { lines, ..Self::default() }
+    }
+
+    /// Stops before feeding the given (1-based) line.
+    pub fn set_stop_line(&mut self, line: Option<usize>) { self.stop_line = line; }
+
+    /// Stops once the VM has executed the given number of instructions.
+    pub fn set_stop_instruction(&mut self, instructions: Option<u64>) { self.stop_instruction = instructions; }
+
+    pub fn stops_at(&self, instructions: u64) -> bool {
+        self.stop_instruction.is_some_and(|stop| instructions >= stop)
+    }
+
+    /// Whether the replay should hand over to the user instead of feeding the next line.
+    pub fn stops_before_next_line(&self, instructions: u64) -> bool {
+        self.stops_at(instructions) || self.stop_line.is_some_and(|stop| self.line + 1 >= stop)
+    }
+
+    /// The next line along with the instruction count it was originally read at.
+    pub fn next_line(&mut self) -> Option<(Option<u64>, String)> {
+        let next = self.lines.pop_front()?;
+        self.line += 1;
+        Some(next)
+    }
+
+    /// Number of lines fed so far.
+    pub fn line(&self) -> usize { self.line }
+
+    pub fn into_lines(self) -> Vec<String> {
+        self.lines.into_iter().map(|(_, line)| line).collect()
+    }
+}
@@ -0,0 +1,515 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use backend::{Error, Policy, Snapshot, SynacorVM};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use serde_json::{json, Value};
+
+const MAGIC: &[u8; 8] = b"SYNSAVE\0";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 20;
+
+const FLAG_COMPRESSED: u16 = 1;
+
+const MEMORY_LEN: usize = 0x8000;
+const MAX_STACK_LEN: usize = 1 << 24;
+const MAX_PENDING_LEN: usize = 1 << 20;
+const MAX_METADATA_LEN: usize = 1 << 20;
+/// Timestamp, binary hash, instruction count, PC, registers, input register,
+/// partial line length, pending input length, metadata length and stack length.
+const FIXED_PAYLOAD_LEN: usize = 8 + 8 + 8 + 2 + 16 + 2 + 2 + 4 + 4 + 4;
+const NOT_WAITING: u16 = 0xFFFF;
+
+/// Lines of game output kept in save metadata.
+pub const RECENT_OUTPUT_LINES: usize = 5;
+
+const LEGACY_HEADER_LEN: usize = 0x800A;
+const IN_OPCODE: u16 = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveInfo {
+    /// 0 for legacy saves, which carry no header.
+    pub version: u16,
+    pub compressed: bool,
+    /// Seconds since the Unix epoch, or 0 if unknown.
+    pub timestamp: u64,
+    /// FNV-1a hash of the binary the game was started from, or 0 if unknown.
+    pub binary_hash: u64,
+    pub instructions: u64,
+    /// The VM was blocked on `in`.
+    pub in_input: bool,
+    /// Input that had been typed but not yet read by the game.
+    pub pending_input: Vec<u8>,
+    pub metadata: Metadata,
+}
+
+/// Descriptive information stored with a save, for telling saves apart.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub room: Option<String>,
+    /// The last few lines the game printed.
+    pub output: Vec<String>,
+    /// Codes found in the game's output so far.
+    pub codes: Vec<String>,
+}
+
+/// Hashes a program so saves can be matched to the binary they came from.
+pub fn binary_hash(bin: &[u16]) -> u64 {
+    bin.iter()
+        .flat_map(|word| word.to_le_bytes())
+        .fold(0xCBF29CE484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001B3))
+}
+
+/// Encodes the VM into the versioned save format:
+///
+/// ```text
+/// magic "SYNSAVE\0" | version u16 | flags u16 | crc32 u32 | payload length u32 | payload
+/// ```
+///
+/// All numbers are little-endian. The checksum covers the payload as stored,
+/// which is zlib-compressed if `FLAG_COMPRESSED` is set. The payload keeps the
+/// VM's input state, so a game saved mid-line resumes exactly where it was.
+pub fn encode(vm: &SynacorVM, pending_input: &[u8], metadata: &Metadata, binary_hash: u64, compress: bool) -> Result<Vec<u8>, &'static str> {
+    let snapshot = vm.snapshot();
+    if snapshot.stack.len() > MAX_STACK_LEN {
+        return Err("stack is too large to save");
+    }
+    if snapshot.partial_line.len() > u16::MAX as usize || pending_input.len() > MAX_PENDING_LEN {
+        return Err("pending input is too long to save");
+    }
+
+    let metadata = json!({ "room": metadata.room, "output": metadata.output, "codes": metadata.codes }).to_string();
+    if metadata.len() > MAX_METADATA_LEN {
+        return Err("save metadata is too large");
+    }
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    let mut payload = Vec::with_capacity(FIXED_PAYLOAD_LEN + 2 * (snapshot.stack.len() + MEMORY_LEN));
+    payload.extend(timestamp.to_le_bytes());
+    payload.extend(binary_hash.to_le_bytes());
+    payload.extend(snapshot.instructions.to_le_bytes());
+    payload.extend(snapshot.pc.to_le_bytes());
+    payload.extend(snapshot.registers.iter().flat_map(|r| r.to_le_bytes()));
+    payload.extend(snapshot.waiting.unwrap_or(NOT_WAITING).to_le_bytes());
+    payload.extend((snapshot.partial_line.len() as u16).to_le_bytes());
+    payload.extend((pending_input.len() as u32).to_le_bytes());
+    payload.extend((metadata.len() as u32).to_le_bytes());
+    payload.extend((snapshot.stack.len() as u32).to_le_bytes());
+    payload.extend(snapshot.stack.iter().flat_map(|v| v.to_le_bytes()));
+    payload.extend(snapshot.memory.iter().flat_map(|v| v.to_le_bytes()));
+    payload.extend(&snapshot.partial_line);
+    payload.extend(pending_input);
+    payload.extend(metadata.bytes());
+
+    let mut flags = 0;
+    if compress {
+        flags |= FLAG_COMPRESSED;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&payload).map_err(|_| "could not compress save state")?;
+        payload = encoder.finish().map_err(|_| "could not compress save state")?;
+    }
+
+    let mut crc = Crc::new();
+    crc.update(&payload);
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    buf.extend(MAGIC);
+    buf.extend(VERSION.to_le_bytes());
+    buf.extend(flags.to_le_bytes());
+    buf.extend(crc.sum().to_le_bytes());
+    buf.extend((payload.len() as u32).to_le_bytes());
+    buf.extend(payload);
+    Ok(buf)
+}
+
+/// Validates a save and loads it into `vm`, which is left untouched on error.
+/// Files without the magic number are read as legacy raw saves.
+pub fn decode(buf: &[u8], vm: &mut SynacorVM) -> Result<SaveInfo, &'static str> {
+    let (info, snapshot) = if buf.starts_with(MAGIC) { parse(buf)? } else { parse_legacy(buf, vm.instructions())? };
+
+    // Only lenient operands let `in` write somewhere other than a register.
+    let register = |dest: u16| (0x8000..0x8008).contains(&dest);
+    if snapshot.waiting.is_some_and(|dest| !register(dest)) && vm.config().operands == Policy::Strict {
+        return Err("save state is waiting for input into an invalid register");
+    }
+
+    restore(vm, &snapshot)?;
+    Ok(info)
+}
+
+/// Validates a save and returns its information without loading it.
+pub fn read_info(buf: &[u8]) -> Result<SaveInfo, &'static str> {
+    if !buf.starts_with(MAGIC) { return Err("not a save state"); }
+    parse(buf).map(|(info, _)| info)
+}
+
+/// All saves in `dir` with a header, newest first. Other files are skipped.
+pub fn list(dir: &Path) -> Result<Vec<(PathBuf, SaveInfo)>, &'static str> {
+    let entries = fs::read_dir(dir).map_err(|_| "could not read directory")?;
+
+    let mut saves = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let info = read_info(&fs::read(&path).ok()?).ok()?;
+            Some((path, info))
+        })
+        .collect::<Vec<_>>();
+
+    saves.sort_by(|(a_path, a), (b_path, b)| b.timestamp.cmp(&a.timestamp).then_with(|| a_path.cmp(b_path)));
+    Ok(saves)
+}
+
+/// The most recent save in `dir` made from the binary with the given hash.
+pub fn latest(dir: &Path, binary_hash: u64) -> Result<Option<PathBuf>, &'static str> {
+    let saves = list(dir)?;
+    Ok(saves.into_iter().find(|(_, info)| info.binary_hash == binary_hash).map(|(path, _)| path))
+}
+
+/// Formats saves as a table. Saves from a binary other than `binary_hash` are marked.
+pub fn table(saves: &[(PathBuf, SaveInfo)], binary_hash: Option<u64>) -> Vec<String> {
+    let name = |path: &PathBuf| path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let width = saves.iter().map(|(path, _)| name(path).len()).max().unwrap_or(0).max(4);
+
+    let mut lines = vec![format!("  {:width$}  {:16}  {:>12}  {:20}  {:5}  {}", "File", "Saved (UTC)", "Instructions", "Room", "Codes", "Last output")];
+    lines.extend(saves.iter().map(|(path, info)| {
+        let other = binary_hash.is_some_and(|hash| hash != info.binary_hash);
+        let last_output = info.metadata.output.last().map_or("", |line| line.as_str());
+
+        format!(
+            "{} {:width$}  {:16}  {:>12}  {:20}  {:5}  {}",
+            if other { '!' } else { ' ' },
+            name(path),
+            format_timestamp(info.timestamp),
+            info.instructions,
+            info.metadata.room.as_deref().unwrap_or("-"),
+            info.metadata.codes.len(),
+            last_output,
+        )
+    }));
+
+    if saves.iter().any(|(_, info)| binary_hash.is_some_and(|hash| hash != info.binary_hash)) {
+        lines.push("! saved from a different binary".into());
+    }
+
+    lines
+}
+
+fn parse(buf: &[u8]) -> Result<(SaveInfo, Snapshot), &'static str> {
+    if buf.len() < HEADER_LEN {
+        return Err("save state header is truncated");
+    }
+
+    let version = u16_at(buf, 8);
+    let flags = u16_at(buf, 10);
+    let checksum = u32_at(buf, 12);
+    let payload_len = u32_at(buf, 16) as usize;
+
+    if version != VERSION { return Err("unsupported save state version"); }
+    if flags & !FLAG_COMPRESSED != 0 { return Err("unknown save state flags"); }
+    if buf.len() != HEADER_LEN + payload_len { return Err("save state length does not match its header"); }
+
+    let mut payload = &buf[HEADER_LEN..];
+    let mut crc = Crc::new();
+    crc.update(payload);
+    if crc.sum() != checksum { return Err("save state checksum mismatch"); }
+
+    let compressed = flags & FLAG_COMPRESSED != 0;
+    let decompressed;
+    if compressed {
+        let max_len = FIXED_PAYLOAD_LEN + 2 * (MAX_STACK_LEN + MEMORY_LEN) + u16::MAX as usize + MAX_PENDING_LEN + MAX_METADATA_LEN;
+        let mut out = Vec::new();
+        ZlibDecoder::new(payload).take(max_len as u64 + 1).read_to_end(&mut out)
+            .map_err(|_| "could not decompress save state")?;
+
+        if out.len() > max_len { return Err("save state is too large"); }
+        decompressed = out;
+        payload = &decompressed;
+    }
+
+    if payload.len() < FIXED_PAYLOAD_LEN { return Err("save state payload is truncated"); }
+
+    let waiting = u16_at(payload, 42);
+    let partial_len = u16_at(payload, 44) as usize;
+    let pending_len = u32_at(payload, 46) as usize;
+    let metadata_len = u32_at(payload, 50) as usize;
+    let stack_len = u32_at(payload, 54) as usize;
+    if stack_len > MAX_STACK_LEN || pending_len > MAX_PENDING_LEN || metadata_len > MAX_METADATA_LEN
+        || payload.len() != FIXED_PAYLOAD_LEN + 2 * (stack_len + MEMORY_LEN) + partial_len + pending_len + metadata_len {
+        return Err("invalid save state payload length");
+    }
+
+    let words = |start: usize, len: usize| (0..len).map(|i| u16_at(payload, start + 2 * i)).collect::<Vec<_>>();
+    let input_start = FIXED_PAYLOAD_LEN + 2 * (stack_len + MEMORY_LEN);
+    let metadata_start = input_start + partial_len + pending_len;
+    let info = SaveInfo {
+        version,
+        compressed,
+        timestamp: u64_at(payload, 0),
+        binary_hash: u64_at(payload, 8),
+        instructions: u64_at(payload, 16),
+        in_input: waiting != NOT_WAITING,
+        pending_input: payload[input_start + partial_len..metadata_start].to_vec(),
+        metadata: parse_metadata(&payload[metadata_start..])?,
+    };
+
+    let snapshot = Snapshot {
+        pc: u16_at(payload, 24),
+        registers: words(26, 8).try_into().unwrap(),
+        stack: words(FIXED_PAYLOAD_LEN, stack_len),
+        memory: words(FIXED_PAYLOAD_LEN + 2 * stack_len, MEMORY_LEN),
+        instructions: info.instructions,
+        waiting: (waiting != NOT_WAITING).then_some(waiting),
+        partial_line: payload[input_start..input_start + partial_len].to_vec(),
+    };
+
+    let pc = snapshot.pc as usize % MEMORY_LEN;
+    if snapshot.waiting.is_some() && snapshot.memory[pc.wrapping_sub(2) % MEMORY_LEN] != IN_OPCODE {
+        return Err("save state is marked as waiting for input, but the PC is not after an `in`");
+    }
+    if snapshot.waiting.is_some_and(|dest| dest != snapshot.memory[pc.wrapping_sub(1) % MEMORY_LEN]) {
+        return Err("save state is waiting for input into a different register than its `in`");
+    }
+
+    Ok((info, snapshot))
+}
+
+fn parse_metadata(buf: &[u8]) -> Result<Metadata, &'static str> {
+    let value = serde_json::from_slice::<Value>(buf).map_err(|_| "invalid save metadata")?;
+    let strings = |key: &str| value[key].as_array()
+        .map(|items| items.iter().filter_map(|s| s.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+
+    Ok(Metadata {
+        room: value["room"].as_str().map(String::from),
+        output: strings("output"),
+        codes: strings("codes"),
+    })
+}
+
+/// Raw `[pc, registers, memory, stack length, stack]` saves from before the
+/// header was introduced. They were written while paused after an `in`, so
+/// the VM is put back into waiting on it.
+fn parse_legacy(buf: &[u8], instructions: u64) -> Result<(SaveInfo, Snapshot), &'static str> {
+    let data = crate::to_u16_vec(buf);
+    if data.len() < LEGACY_HEADER_LEN || data.len() < LEGACY_HEADER_LEN + data[0x8009] as usize {
+        return Err("Invalid data length");
+    }
+
+    let stack_len = data[0x8009] as usize;
+    let memory = &data[0x9..0x8009];
+    let pc = data[0];
+
+    let in_pc = pc.wrapping_sub(2) & 0x7FFF;
+    let in_input = memory[in_pc as usize] == IN_OPCODE;
+
+    let snapshot = Snapshot {
+        pc,
+        registers: data[0x1..0x9].try_into().unwrap(),
+        stack: data[LEGACY_HEADER_LEN..LEGACY_HEADER_LEN + stack_len].to_vec(),
+        memory: memory.to_vec(),
+        instructions,
+        waiting: in_input.then(|| memory[(in_pc as usize + 1) % MEMORY_LEN]),
+        partial_line: Vec::new(),
+    };
+
+    let info = SaveInfo {
+        version: 0,
+        compressed: false,
+        timestamp: 0,
+        binary_hash: 0,
+        instructions,
+        in_input,
+        pending_input: Vec::new(),
+        metadata: Metadata::default(),
+    };
+
+    Ok((info, snapshot))
+}
+
+fn restore(vm: &mut SynacorVM, snapshot: &Snapshot) -> Result<(), &'static str> {
+    vm.restore(snapshot).map_err(|e| match e {
+        Error::InvalidSnapshot(reason) => reason,
+        _ => "invalid save state",
+    })
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time.
+fn format_timestamp(timestamp: u64) -> String {
+    if timestamp == 0 { return "-".into(); }
+
+    // Civil date from days since the epoch, see Howard Hinnant's `civil_from_days`.
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    let secs = timestamp % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, secs / 3600, secs % 3600 / 60)
+}
+
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `in r0` then `jmp 0`, forever.
+    const ECHO: [u16; 4] = [IN_OPCODE, 0x8000, 6, 0];
+
+    /// A VM blocked on its second `in`, with "g" read of the current line.
+    fn waiting_vm() -> SynacorVM {
+        let mut vm = SynacorVM::new();
+        vm.load_binary(&ECHO);
+        vm.stack_mut().push(7).unwrap();
+        while vm.waiting().is_none() { vm.step().unwrap(); }
+        vm.write_input(b'g').unwrap();
+        while vm.waiting().is_none() { vm.step().unwrap(); }
+        vm
+    }
+
+    fn metadata() -> Metadata {
+        Metadata { room: Some("Foothills".into()), output: vec!["What do you do?".into()], codes: vec!["ZbXqLmTrVwPk".into()] }
+    }
+
+    fn uncompressed() -> Vec<u8> {
+        encode(&waiting_vm(), b"o north\n", &metadata(), 0x1234, false).unwrap()
+    }
+
+    /// Changes the payload of an uncompressed save and fixes up its checksum.
+    fn patch(buf: &mut [u8], at: usize, bytes: &[u8]) {
+        buf[HEADER_LEN + at..HEADER_LEN + at + bytes.len()].copy_from_slice(bytes);
+
+        let mut crc = Crc::new();
+        crc.update(&buf[HEADER_LEN..]);
+        buf[12..16].copy_from_slice(&crc.sum().to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let vm = waiting_vm();
+
+        for compress in [false, true] {
+            let buf = encode(&vm, b"o north\n", &metadata(), 0x1234, compress).unwrap();
+            let mut loaded = SynacorVM::new();
+            let info = decode(&buf, &mut loaded).unwrap();
+
+            assert_eq!(loaded.snapshot(), vm.snapshot());
+            assert_eq!(loaded.state_hash(), vm.state_hash());
+            assert_eq!(loaded.partial_line(), b"g");
+            assert_eq!((info.version, info.compressed, info.binary_hash), (VERSION, compress, 0x1234));
+            assert_eq!(info.instructions, vm.instructions());
+            assert!(info.in_input);
+            assert_eq!(info.pending_input, b"o north\n");
+            assert_eq!(info.metadata, metadata());
+            assert_eq!(read_info(&buf).unwrap(), info);
+        }
+    }
+
+    #[test]
+    fn legacy() {
+        let vm = waiting_vm();
+        let mut data = vec![vm.pc()];
+        data.extend(vm.registers());
+        data.extend(vm.memory().iter());
+        data.push(1);
+        data.push(7);
+
+        let mut loaded = SynacorVM::new();
+        let info = decode(&crate::to_u8_vec(&data), &mut loaded).unwrap();
+
+        assert_eq!(info.version, 0);
+        assert!(info.in_input);
+        assert_eq!(loaded.waiting(), Some(0x8000));
+        assert_eq!(loaded.stack().contents(), [7]);
+        assert_eq!(loaded.memory(), vm.memory());
+
+        assert!(decode(&crate::to_u8_vec(&data[..0x8000]), &mut SynacorVM::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let mut vm = waiting_vm();
+        let before = vm.snapshot();
+        let rejects = |buf: &[u8], vm: &mut SynacorVM| decode(buf, vm).unwrap_err();
+
+        let buf = uncompressed();
+        let mut flipped = buf.clone();
+        flipped[HEADER_LEN + 100] ^= 1;
+        assert_eq!(rejects(&flipped, &mut vm), "save state checksum mismatch");
+
+        rejects(&buf[..buf.len() - 1], &mut vm);
+        rejects(&buf[..HEADER_LEN - 1], &mut vm);
+
+        let mut version = buf.clone();
+        version[8] = VERSION as u8 + 1;
+        rejects(&version, &mut vm);
+
+        let mut flags = buf.clone();
+        flags[10] = 0x80;
+        rejects(&flags, &mut vm);
+
+        let mut compressed = encode(&waiting_vm(), b"", &Metadata::default(), 0, true).unwrap();
+        let len = compressed.len();
+        compressed[len - 3] ^= 0xFF;
+        rejects(&compressed, &mut vm);
+
+        // Field values that are consistent with the checksum but not with the machine.
+        let mut pc = buf.clone();
+        patch(&mut pc, 24, &0x8002u16.to_le_bytes());
+        assert_eq!(rejects(&pc, &mut vm), "PC out of range");
+
+        let mut waiting = buf.clone();
+        patch(&mut waiting, 42, &0x8001u16.to_le_bytes());
+        assert_eq!(rejects(&waiting, &mut vm), "save state is waiting for input into a different register than its `in`");
+
+        let mut stack_len = buf.clone();
+        patch(&mut stack_len, 54, &2u32.to_le_bytes());
+        assert_eq!(rejects(&stack_len, &mut vm), "invalid save state payload length");
+
+        let mut metadata = buf.clone();
+        let len = metadata.len() - HEADER_LEN;
+        patch(&mut metadata, len - 1, b"!");
+        assert_eq!(rejects(&metadata, &mut vm), "invalid save metadata");
+
+        assert_eq!(vm.snapshot(), before);
+        // Registers can legally hold values above 15 bits.
+        let mut register = buf;
+        patch(&mut register, 26, &0x8000u16.to_le_bytes());
+        decode(&register, &mut vm).unwrap();
+        assert_eq!(vm.registers()[0], 0x8000);
+    }
+
+    #[test]
+    fn rejects_invalid_input_register() {
+        let mut bin = ECHO;
+        bin[1] = 5;
+        let mut vm = SynacorVM::with_config(backend::VmConfig::lenient());
+        vm.load_binary(&bin);
+        vm.step().unwrap();
+
+        let buf = encode(&vm, b"", &Metadata::default(), 0, false).unwrap();
+        assert_eq!(decode(&buf, &mut SynacorVM::new()).unwrap_err(), "save state is waiting for input into an invalid register");
+        assert_eq!(decode(&buf, &mut SynacorVM::with_config(backend::VmConfig::lenient())).unwrap().version, VERSION);
+    }
+}
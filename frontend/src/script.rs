@@ -0,0 +1,237 @@
+use std::{fmt, fs};
+use backend::{parse_room, GameSession, SessionState, STEP_LIMIT};
+use colored::Colorize;
+use crate::save::{self, Metadata};
+use crate::walkthrough::find_codes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Memory(usize),
+    Register(usize),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Memory(addr) => write!(f, "mem[{:#06X}]", addr),
+            Target::Register(reg) => write!(f, "reg[{}]", reg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Send(String),
+    Expect(String),
+    ExpectNot(String),
+    Assert { target: Target, equal: bool, value: u16 },
+    Snapshot(String),
+}
+
+/// A regression test script, one statement per line:
+///
+/// ```text
+/// send "go north"
+/// expect "== Foothills =="
+/// expect-not "You die"
+/// assert mem[0x0AAC] == 3
+/// assert reg[7] != 0
+/// snapshot ok.sav
+/// ```
+///
+/// Expectations are checked against the output of the last `send`, or the boot
+/// output before the first one. Blank lines and `#` comments are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    statements: Vec<(usize, Statement)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut statements = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let statement = parse_statement(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            statements.push((i + 1, statement));
+        }
+
+        Ok(Self { statements })
+    }
+
+    /// Runs every statement, printing failures as they happen. Stops early only
+    /// if the game can no longer take input.
+    /// `binary_hash` is recorded in snapshots, see [`save::binary_hash`].
+    pub fn run(&self, session: &mut GameSession, binary_hash: u64) -> Report {
+        session.set_step_limit(Some(STEP_LIMIT));
+
+        let mut report = Report::default();
+        let mut output = session.run();
+
+        for (line, statement) in &self.statements {
+            let result = match statement {
+                Statement::Send(command) => match session.state() {
+                    SessionState::WaitingForInput => {
+                        output = session.send(command);
+                        continue;
+                    }
+                    state => Err(format!("cannot send {:?}, the game is no longer running: {:?}", command, state)),
+                },
+                Statement::Expect(text) => {
+                    if output.contains(text.as_str()) { Ok(()) } else {
+                        Err(format!("expected output containing {:?}\n{}", text, diff_lines(text, &output)))
+                    }
+                }
+                Statement::ExpectNot(text) => {
+                    if !output.contains(text.as_str()) { Ok(()) } else {
+                        Err(format!("expected output not containing {:?}\n{}", text, diff_lines("", &output)))
+                    }
+                }
+                Statement::Assert { target, equal, value } => {
+                    let actual = match *target {
+                        Target::Memory(addr) => session.vm().memory()[addr],
+                        Target::Register(reg) => session.vm().registers()[reg],
+                    };
+
+                    if (actual == *value) == *equal { Ok(()) } else {
+                        let op = if *equal { "==" } else { "!=" };
+                        Err(format!("expected {} {} {:#06X}, was {:#06X}", target, op, value, actual))
+                    }
+                }
+                Statement::Snapshot(path) => {
+                    let lines = output.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<_>>();
+                    let metadata = Metadata {
+                        room: parse_room(&output).map(|room| room.name),
+                        output: lines[lines.len().saturating_sub(save::RECENT_OUTPUT_LINES)..].iter().map(|&line| line.into()).collect(),
+                        codes: find_codes(&output),
+                    };
+
+                    save::encode(session.vm(), &[], &metadata, binary_hash, true)
+                        .map_err(String::from)
+                        .and_then(|buf| fs::write(path, buf).map_err(|e| format!("could not write {}: {}", path, e)))
+                }
+            };
+
+            match result {
+                Ok(()) => report.passed += 1,
+                Err(msg) => {
+                    report.failed += 1;
+                    println!("{} {}", format!("line {}:", line).red().bold(), msg);
+
+                    if matches!(statement, Statement::Send(_)) { break; }
+                }
+            }
+        }
+
+        report
+    }
+}
+
+fn parse_statement(line: &str) -> Result<Statement, String> {
+    let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+
+    match keyword {
+        "send" => Ok(Statement::Send(parse_string(rest)?)),
+        "expect" => Ok(Statement::Expect(parse_string(rest)?)),
+        "expect-not" => Ok(Statement::ExpectNot(parse_string(rest)?)),
+        "snapshot" => Ok(Statement::Snapshot(rest.trim_matches('"').into())),
+        "assert" => {
+            let words = rest.split_whitespace().collect::<Vec<_>>();
+            let [target, op, value] = words[..] else {
+                return Err("expected assert mem[<addr>] == <value>".into());
+            };
+
+            let (kind, index) = target.strip_suffix(']').and_then(|t| t.split_once('['))
+                .ok_or("expected mem[<addr>] or reg[<n>]")?;
+            let index = parse_number(index)? as usize;
+
+            let target = match kind {
+                "mem" if index < 0x8000 => Target::Memory(index),
+                "reg" if index < 8 => Target::Register(index),
+                "mem" | "reg" => return Err(format!("{} out of range", target)),
+                _ => return Err(format!("unknown assertion target {}", kind)),
+            };
+
+            let equal = match op {
+                "==" => true,
+                "!=" => false,
+                _ => return Err(format!("unknown operator {}", op)),
+            };
+
+            Ok(Statement::Assert { target, equal, value: parse_number(value)? })
+        }
+        _ => Err(format!("unknown statement {}", keyword)),
+    }
+}
+
+/// Parses a double-quoted string with `\n`, `\"` and `\\` escapes.
+fn parse_string(s: &str) -> Result<String, String> {
+    let inner = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).ok_or("expected a quoted string")?;
+
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(c @ ('"' | '\\')) => out.push(c),
+            _ => return Err("invalid escape sequence".into()),
+        }
+    }
+
+    Ok(out)
+}
+
+fn parse_number(s: &str) -> Result<u16, String> {
+    let val = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    val.map_err(|_| format!("invalid number {}", s))
+}
+
+/// Line diff between the expected text and the actual output, based on the
+/// longest common subsequence of lines.
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let a = expected.lines().collect::<Vec<_>>();
+    let b = actual.lines().collect::<Vec<_>>();
+
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push_str(&format!("  {}\n", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("{}\n", format!("- {}", a[i]).red()));
+            i += 1;
+        } else {
+            out.push_str(&format!("{}\n", format!("+ {}", b[j]).green()));
+            j += 1;
+        }
+    }
+
+    out
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+
+const INDEX_FILE: &str = "timeline.json";
+/// Input lines shown per node in the tree before eliding the rest.
+const SHOWN_INPUTS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub id: usize,
+    pub parent: Option<usize>,
+    pub label: Option<String>,
+    /// Input lines entered between the parent and this node.
+    pub inputs: Vec<String>,
+    pub room: Option<String>,
+    pub instructions: u64,
+}
+
+/// A tree of save states kept in a project directory. Each node's state is
+/// stored as `<id>.sav` next to a `timeline.json` index.
+#[derive(Debug)]
+pub struct Timeline {
+    dir: PathBuf,
+    nodes: Vec<Node>,
+    current: Option<usize>,
+}
+
+impl Timeline {
+    /// Opens the project in `dir`, creating the directory if needed.
+    pub fn open(dir: &Path) -> Result<Self, &'static str> {
+        fs::create_dir_all(dir).map_err(|_| "could not create project directory")?;
+
+        let mut timeline = Self { dir: dir.to_path_buf(), nodes: Vec::new(), current: None };
+        let index = match fs::read_to_string(dir.join(INDEX_FILE)) {
+            Ok(index) => index,
+            Err(_) => return Ok(timeline),
+        };
+
+        let index = serde_json::from_str::<Value>(&index).map_err(|_| "invalid timeline file")?;
+        timeline.nodes = index["nodes"].as_array().ok_or("invalid timeline file")?
+            .iter()
+            .map(parse_node)
+            .collect::<Option<Vec<_>>>()
+            .ok_or("invalid timeline file")?;
+        timeline.current = index["current"].as_u64().map(|id| id as usize);
+
+        let valid = timeline.nodes.iter().enumerate().all(|(i, node)| node.id == i && node.parent.is_none_or(|p| p < i))
+            && timeline.current.is_none_or(|id| id < timeline.nodes.len());
+        if !valid { return Err("invalid timeline file"); }
+
+        Ok(timeline)
+    }
+
+    pub fn current(&self) -> Option<usize> { self.current }
+
+    pub fn node(&self, id: usize) -> Option<&Node> { self.nodes.get(id) }
+
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+    /// Adds `state`, an encoded save, as a child of the current node and makes it current.
+    pub fn add(&mut self, state: &[u8], inputs: Vec<String>, room: Option<String>, instructions: u64) -> Result<usize, &'static str> {
+        let id = self.nodes.len();
+        fs::write(self.state_path(id), state).map_err(|_| "could not write timeline state")?;
+
+        self.nodes.push(Node { id, parent: self.current, label: None, inputs, room, instructions });
+        self.current = Some(id);
+        self.save_index()?;
+        Ok(id)
+    }
+
+    pub fn label(&mut self, id: usize, label: &str) -> Result<(), &'static str> {
+        if self.nodes.iter().any(|node| node.id != id && node.label.as_deref() == Some(label)) {
+            return Err("label already in use");
+        }
+
+        self.nodes.get_mut(id).ok_or("no such node")?.label = Some(label.into());
+        self.save_index()
+    }
+
+    /// Looks a node up by id or label.
+    pub fn find(&self, key: &str) -> Option<usize> {
+        self.nodes.iter()
+            .find(|node| node.label.as_deref() == Some(key))
+            .map(|node| node.id)
+            .or_else(|| key.parse().ok().filter(|&id| id < self.nodes.len()))
+    }
+
+    /// Makes `id` the current node without loading its state, or clears it with `None`.
+    pub fn set_current(&mut self, id: Option<usize>) -> Result<(), &'static str> {
+        if id.is_some_and(|id| id >= self.nodes.len()) { return Err("no such node"); }
+
+        self.current = id;
+        self.save_index()
+    }
+
+    /// Reads a node's encoded save.
+    pub fn state(&self, id: usize) -> Result<Vec<u8>, &'static str> {
+        if id >= self.nodes.len() { return Err("no such node"); }
+        fs::read(self.state_path(id)).map_err(|_| "could not read timeline state")
+    }
+
+    /// Reads a node's state and makes it the current node.
+    pub fn checkout(&mut self, id: usize) -> Result<Vec<u8>, &'static str> {
+        let state = self.state(id)?;
+        self.current = Some(id);
+        self.save_index()?;
+        Ok(state)
+    }
+
+    /// Draws the tree, one node per line, marking the current node.
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for root in self.children(None) {
+            self.render_node(root, "", None, &mut lines);
+        }
+
+        lines
+    }
+
+    fn render_node(&self, id: usize, prefix: &str, last: Option<bool>, lines: &mut Vec<String>) {
+        let node = &self.nodes[id];
+        let connector = match last {
+            None => "",
+            Some(true) => "└── ",
+            Some(false) => "├── ",
+        };
+
+        let mut line = format!("{}{}{}", prefix, connector, node.id);
+        if let Some(label) = &node.label { line.push_str(&format!(" \"{}\"", label)); }
+        if let Some(room) = &node.room { line.push_str(&format!(" [{}]", room)); }
+
+        if !node.inputs.is_empty() {
+            let shown = node.inputs.iter().rev().take(SHOWN_INPUTS).rev().cloned().collect::<Vec<_>>();
+            let elided = if node.inputs.len() > SHOWN_INPUTS { "..., " } else { "" };
+            line.push_str(&format!(" +{} ({}{})", node.inputs.len(), elided, shown.join(", ")));
+        }
+
+        if self.current == Some(id) { line.push_str("  <- current"); }
+        lines.push(line);
+
+        let child_prefix = match last {
+            None => prefix.to_string(),
+            Some(true) => format!("{}    ", prefix),
+            Some(false) => format!("{}│   ", prefix),
+        };
+
+        let children = self.children(Some(id));
+        for (i, &child) in children.iter().enumerate() {
+            self.render_node(child, &child_prefix, Some(i + 1 == children.len()), lines);
+        }
+    }
+
+    fn children(&self, parent: Option<usize>) -> Vec<usize> {
+        self.nodes.iter().filter(|node| node.parent == parent).map(|node| node.id).collect()
+    }
+
+    fn state_path(&self, id: usize) -> PathBuf {
+        self.dir.join(format!("{}.sav", id))
+    }
+
+    fn save_index(&self) -> Result<(), &'static str> {
+        let nodes = self.nodes.iter().map(|node| json!({
+            "id": node.id,
+            "parent": node.parent,
+            "label": node.label,
+            "inputs": node.inputs,
+            "room": node.room,
+            "instructions": node.instructions,
+        })).collect::<Vec<_>>();
+
+        let index = json!({ "current": self.current, "nodes": nodes });
+        fs::write(self.dir.join(INDEX_FILE), serde_json::to_string_pretty(&index).unwrap())
+            .map_err(|_| "could not write timeline file")
+    }
+}
+
+fn parse_node(value: &Value) -> Option<Node> {
+    Some(Node {
+        id: value["id"].as_u64()? as usize,
+        parent: value["parent"].as_u64().map(|id| id as usize),
+        label: value["label"].as_str().map(String::from),
+        inputs: value["inputs"].as_array()?.iter().map(|s| s.as_str().map(String::from)).collect::<Option<_>>()?,
+        room: value["room"].as_str().map(String::from),
+        instructions: value["instructions"].as_u64()?,
+    })
+}
@@ -0,0 +1,395 @@
+use std::error::Error;
+use std::mem;
+use std::time::Duration;
+use backend::{disassembler, Event, Memory};
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use crate::{split_words, TerminalVM, COMMAND_PREFIX};
+
+const STEPS_PER_FRAME: usize = 0x4000;
+const CONSOLE_LIMIT: usize = 0x10000;
+const MEMORY_ROW_LEN: usize = 8;
+const MEMORY_PAGE_ROWS: usize = 16;
+const DISASSEMBLY_LOOKBEHIND: usize = 12;
+
+pub fn run(vm: &mut TerminalVM, breakpoints: &[u16]) -> Result<(), Box<dyn Error>> {
+    colored::control::set_override(false);
+    vm.messages = Some(Vec::new());
+
+    let mut terminal = ratatui::init();
+    let result = Debugger::new(vm, breakpoints).run(&mut terminal);
+    ratatui::restore();
+
+    vm.messages = None;
+    colored::control::unset_override();
+    result
+}
+
+struct Debugger<'a> {
+    term: &'a mut TerminalVM,
+    breakpoints: &'a [u16],
+    console: String,
+    command: String,
+    halted: bool,
+    prev_registers: [u16; 8],
+    memory_offset: usize,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(term: &'a mut TerminalVM, breakpoints: &'a [u16]) -> Self {
+        let prev_registers = *term.vm.registers();
+
+        Self {
+            term,
+            breakpoints,
+            console: String::new(),
+            command: String::new(),
+            halted: false,
+            prev_registers,
+            memory_offset: 0,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        if self.breakpoints.contains(&self.term.vm.pc()) {
+            self.term.debug = true;
+            self.term.notify("Breakpoint reached, debug mode enabled.");
+        }
+
+        while !self.term.quit {
+            self.flush_messages();
+            terminal.draw(|f| self.draw(f))?;
+
+            let timeout = if self.is_running() { Duration::ZERO } else { Duration::from_millis(250) };
+            if event::poll(timeout)? {
+                if let TermEvent::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key.code, key.modifiers);
+                    }
+                }
+            }
+
+            if self.is_running() {
+                self.advance(STEPS_PER_FRAME);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        !self.term.debug && !self.halted && (self.term.vm.waiting().is_none() || !self.term.input_queue.is_empty())
+    }
+
+    fn advance(&mut self, budget: usize) {
+        self.prev_registers = *self.term.vm.registers();
+
+        for _ in 0..budget {
+            if self.halted || !self.feed_input() {
+                return;
+            }
+
+            self.term.pc_history.push(self.term.vm.pc());
+
+            let status = match self.term.vm.step() {
+                Ok(status) => status,
+                Err(fault) => {
+                    self.term.enter_post_mortem(fault);
+                    return;
+                }
+            };
+
+            self.term.fault = None;
+            match status {
+                Some(Event::Halt) => {
+                    self.halted = true;
+                    self.term.notify("Program halted.");
+                    return;
+                }
+                Some(Event::Output(val)) => {
+                    self.console.push(val as char);
+                    self.term.response.push(val as char);
+                }
+                Some(Event::Input(_)) => {
+                    if self.term.input_queue.is_empty() { self.term.finish_response(); }
+
+                    if !self.feed_input() && self.term.fault.is_some() { return; }
+                }
+                None => {}
+            }
+
+            if self.breakpoints.contains(&self.term.vm.pc()) {
+                self.term.debug = true;
+                self.term.notify("Breakpoint reached, debug mode enabled.");
+            }
+
+            if self.term.debug { break; }
+        }
+    }
+
+    /// Completes a pending `in` instruction, returning whether the VM can keep going.
+    fn feed_input(&mut self) -> bool {
+        if self.term.vm.waiting().is_none() {
+            return true;
+        }
+
+        match self.term.consume_input() {
+            Some(result) => {
+                if let Err(fault) = result {
+                    self.term.enter_post_mortem(fault);
+                    return false;
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        match code {
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => self.term.quit = true,
+            KeyCode::Char(c) => self.command.push(c),
+            KeyCode::Backspace => { self.command.pop(); }
+            KeyCode::Esc => self.command.clear(),
+            KeyCode::Enter => self.submit(),
+            KeyCode::F(10) => {
+                self.term.debug = true;
+                self.advance(1);
+            }
+            KeyCode::Up => self.scroll_memory(-1),
+            KeyCode::Down => self.scroll_memory(1),
+            KeyCode::PageUp => self.scroll_memory(-(MEMORY_PAGE_ROWS as isize)),
+            KeyCode::PageDown => self.scroll_memory(MEMORY_PAGE_ROWS as isize),
+            _ => {}
+        }
+    }
+
+    fn submit(&mut self) {
+        let input = mem::take(&mut self.command).trim().to_string();
+
+        if input.is_empty() {
+            if self.term.debug { self.advance(1); }
+            return;
+        }
+
+        if !input.starts_with(COMMAND_PREFIX) {
+            self.console.push_str(&input);
+            self.console.push('\n');
+            self.term.write_input(&input);
+            self.term.saved = false;
+            return;
+        }
+
+        let words = split_words(input);
+        if words[0] == ":m" { // memory view (address)
+            match words.get(1).map(|s| usize::from_str_radix(s, 16)) {
+                Some(Ok(addr)) if addr < 0x8000 => self.memory_offset = addr - addr % MEMORY_ROW_LEN,
+                _ => self.term.notify("Error: invalid address"),
+            }
+        } else {
+            // Undoing or loading a state revives a halted game.
+            let hash = self.term.vm.state_hash();
+            self.term.run_command(words);
+            if self.term.vm.state_hash() != hash { self.halted = false; }
+        }
+    }
+
+    fn scroll_memory(&mut self, rows: isize) {
+        let offset = self.memory_offset as isize + rows * MEMORY_ROW_LEN as isize;
+        self.memory_offset = offset.clamp(0, (0x8000 - MEMORY_ROW_LEN) as isize) as usize;
+    }
+
+    fn flush_messages(&mut self) {
+        let messages = match &mut self.term.messages {
+            Some(messages) => mem::take(messages),
+            None => return,
+        };
+
+        for msg in messages {
+            if !self.console.is_empty() && !self.console.ends_with('\n') {
+                self.console.push('\n');
+            }
+
+            self.console.push_str(&msg);
+            self.console.push('\n');
+        }
+
+        if self.console.len() > CONSOLE_LIMIT {
+            let cut = self.console.len() - CONSOLE_LIMIT;
+            let cut = self.console[cut..].find('\n').map_or(cut, |i| cut + i + 1);
+            self.console.drain(..cut);
+        }
+    }
+
+    fn draw(&self, f: &mut Frame) {
+        let [main, command_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(f.area());
+        let [top, bottom] = Layout::vertical([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(main);
+        let [disassembly_area, side] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(top);
+        let [registers_area, stack_area] = Layout::vertical([Constraint::Length(5), Constraint::Min(0)]).areas(side);
+        let [console_area, memory_area] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(bottom);
+
+        self.draw_disassembly(f, disassembly_area);
+        self.draw_registers(f, registers_area);
+        self.draw_stack(f, stack_area);
+        self.draw_console(f, console_area);
+        self.draw_memory(f, memory_area);
+        self.draw_command(f, command_area);
+    }
+
+    fn draw_disassembly(&self, f: &mut Frame, area: Rect) {
+        let memory = self.term.vm.memory();
+        let pc = self.term.vm.pc() as usize;
+        let rows = area.height.saturating_sub(2) as usize;
+
+        let mut lines = Vec::with_capacity(rows);
+        let mut addr = disassembly_start(memory, pc);
+
+        while lines.len() < rows && addr + 4 <= memory.len() {
+            let (assembly, incr) = disassembler::to_assembly_instruction(addr, memory);
+            let breakpoint = self.breakpoints.contains(&(addr as u16));
+
+            let text = format!(
+                "{}{} {:04X}    {}",
+                if breakpoint { '●' } else { ' ' },
+                if addr == pc { '▶' } else { ' ' },
+                addr,
+                assembly,
+            );
+
+            let style = if addr == pc {
+                Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD)
+            } else if breakpoint {
+                Style::new().fg(Color::Red)
+            } else {
+                Style::new()
+            };
+
+            lines.push(Line::styled(text, style));
+            addr += incr;
+        }
+
+        f.render_widget(Paragraph::new(lines).block(titled("Disassembly")), area);
+    }
+
+    fn draw_registers(&self, f: &mut Frame, area: Rect) {
+        let registers = self.term.vm.registers();
+
+        let mut lines = registers.chunks(4).enumerate().map(|(row, chunk)| {
+            let spans = chunk.iter().enumerate().flat_map(|(col, &val)| {
+                let i = row * 4 + col;
+                let style = if val != self.prev_registers[i] {
+                    Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                } else {
+                    Style::new()
+                };
+
+                [Span::raw(format!("R{} ", i)), Span::styled(format!("{:04X}  ", val), style)]
+            }).collect::<Vec<_>>();
+
+            Line::from(spans)
+        }).collect::<Vec<_>>();
+
+        lines.push(Line::raw(format!("PC {:04X}  SP {:04X}", self.term.vm.pc(), self.term.vm.stack().pointer())));
+        f.render_widget(Paragraph::new(lines).block(titled("Registers")), area);
+    }
+
+    fn draw_stack(&self, f: &mut Frame, area: Rect) {
+        let memory = self.term.vm.memory();
+        let contents = self.term.vm.stack().contents();
+        let mut frame = 0;
+
+        let lines = contents.iter().enumerate().rev().map(|(i, &val)| {
+            let addr = val as usize;
+            let is_return = (2..memory.len()).contains(&addr) && memory[addr - 2] == 17;
+
+            if is_return {
+                let text = format!("{:04X}: {:04X}  #{} return, called at {:04X}", i, val, frame, addr - 2);
+                frame += 1;
+                Line::styled(text, Style::new().fg(Color::Magenta))
+            } else {
+                Line::raw(format!("{:04X}: {:04X}", i, val))
+            }
+        }).collect::<Vec<_>>();
+
+        f.render_widget(Paragraph::new(lines).block(titled("Stack")), area);
+    }
+
+    fn draw_console(&self, f: &mut Frame, area: Rect) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let lines = self.console.lines().collect::<Vec<_>>();
+        let visible = lines[lines.len().saturating_sub(rows)..].iter()
+            .map(|&l| Line::raw(l))
+            .collect::<Vec<_>>();
+
+        let title = match self.term.room() {
+            Some(room) => format!("Output - {}", room.name),
+            None => "Output".into(),
+        };
+
+        f.render_widget(Paragraph::new(visible).block(titled(&title)), area);
+    }
+
+    fn draw_memory(&self, f: &mut Frame, area: Rect) {
+        let memory = self.term.vm.memory();
+        let pc = self.term.vm.pc() as usize;
+        let rows = area.height.saturating_sub(2) as usize;
+
+        let lines = (0..rows)
+            .map(|row| self.memory_offset + row * MEMORY_ROW_LEN)
+            .take_while(|&addr| addr < memory.len())
+            .map(|addr| {
+                let mut spans = vec![Span::styled(format!("{:04X}:", addr), Style::new().fg(Color::DarkGray))];
+
+                for (i, val) in memory.read(addr..addr + MEMORY_ROW_LEN).enumerate() {
+                    let style = if addr + i == pc { Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD) } else { Style::new() };
+                    spans.push(Span::styled(format!(" {:04X}", val), style));
+                }
+
+                Line::from(spans)
+            })
+            .collect::<Vec<_>>();
+
+        f.render_widget(Paragraph::new(lines).block(titled("Memory")), area);
+    }
+
+    fn draw_command(&self, f: &mut Frame, area: Rect) {
+        let status = if self.halted {
+            "Halted"
+        } else if self.term.debug {
+            "Paused"
+        } else if self.term.vm.waiting().is_some() && self.term.input_queue.is_empty() {
+            "Waiting for input"
+        } else {
+            "Running"
+        };
+
+        let prompt = format!("> {}", self.command);
+        f.set_cursor_position((area.x + 1 + prompt.chars().count() as u16, area.y + 1));
+        f.render_widget(Paragraph::new(prompt).block(titled(status)), area);
+    }
+}
+
+fn titled(title: &str) -> Block<'_> {
+    Block::new().borders(Borders::ALL).title(title)
+}
+
+/// Finds the furthest address behind `pc` from which decoding lands exactly on `pc`.
+fn disassembly_start(memory: &Memory, pc: usize) -> usize {
+    for back in (1..=DISASSEMBLY_LOOKBEHIND.min(pc)).rev() {
+        let mut addr = pc - back;
+        while addr < pc {
+            addr += disassembler::to_assembly_instruction(addr, memory).1;
+        }
+
+        if addr == pc {
+            return pc - back;
+        }
+    }
+
+    pc
+}
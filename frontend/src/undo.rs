@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+use backend::{Room, SynacorVM};
+
+/// The VM and the room the player was in before a command, and the command itself.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub vm: SynacorVM,
+    pub room: Option<Room>,
+    pub command: String,
+}
+
+/// Automatic per-command checkpoints. Memory is copy-on-write, so each one
+/// only costs the pages its command changed.
+#[derive(Debug, Clone)]
+pub struct UndoHistory {
+    undo: VecDeque<Checkpoint>,
+    redo: Vec<Checkpoint>,
+    limit: usize,
+    line_start: Option<(SynacorVM, Option<Room>)>,
+}
+
+impl UndoHistory {
+    pub fn new(limit: usize) -> Self {
+        Self { undo: VecDeque::new(), redo: Vec::new(), limit, line_start: None }
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.undo.drain(..self.undo.len().saturating_sub(limit));
+    }
+
+    /// Called before `in` reads the first byte of a line.
+    pub fn line_started(&mut self, vm: &SynacorVM, room: Option<&Room>) {
+        if self.limit > 0 { self.line_start = Some((vm.clone(), room.cloned())); }
+    }
+
+    /// Called once `in` has read the newline ending `command`.
+    pub fn line_finished(&mut self, command: String) {
+        let (vm, room) = match self.line_start.take() {
+            Some(start) => start,
+            None => return,
+        };
+
+        self.push_undo(Checkpoint { vm, room, command });
+        self.redo.clear();
+    }
+
+    /// Steps back one command from `current`, which can then be redone.
+    pub fn undo(&mut self, current: SynacorVM, room: Option<Room>) -> Option<Checkpoint> {
+        let checkpoint = self.undo.pop_back()?;
+        self.line_start = None;
+        self.redo.push(Checkpoint { vm: current, room, command: checkpoint.command.clone() });
+        Some(checkpoint)
+    }
+
+    /// Reapplies the most recently undone command, returning the state after it.
+    pub fn redo(&mut self, current: SynacorVM, room: Option<Room>) -> Option<Checkpoint> {
+        let checkpoint = self.redo.pop()?;
+        self.line_start = None;
+        self.push_undo(Checkpoint { vm: current, room, command: checkpoint.command.clone() });
+        Some(checkpoint)
+    }
+
+    /// Forgets every checkpoint, e.g. once the VM has been replaced by a loaded state.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.line_start = None;
+    }
+
+    pub fn len(&self) -> usize { self.undo.len() }
+
+    pub fn is_empty(&self) -> bool { self.undo.is_empty() }
+
+    fn push_undo(&mut self, checkpoint: Checkpoint) {
+        if self.limit == 0 { return; }
+        if self.undo.len() == self.limit { self.undo.pop_front(); }
+        self.undo.push_back(checkpoint);
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use backend::{parse_look, parse_room, GameSession, Memory, SessionState, STEP_LIMIT};
+use colored::Colorize;
+
+const CODE_LEN: usize = 12;
+
+/// A built-in solver that can be invoked from a walkthrough with `@step <name>`.
+pub trait PuzzleStep {
+    fn name(&self) -> &'static str;
+
+    /// Plays the puzzle, returning everything the game printed along the way.
+    fn run(&self, session: &mut GameSession) -> Result<String, String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Command(String),
+    ExpectRoom(String),
+    ExpectOutput(String),
+    Step(String),
+}
+
+pub struct Walkthrough {
+    actions: Vec<(usize, Action)>,
+    steps: HashMap<&'static str, Box<dyn PuzzleStep>>,
+}
+
+impl Walkthrough {
+    /// Parses a walkthrough file. Every line is a game command, except for
+    /// blank lines, `#` comments and these directives:
+    ///
+    /// * `@room <name>` - the last room entered must be `<name>`
+    /// * `@expect <text>` - the output of the last command must contain `<text>`
+    /// * `@step <name>` - runs a built-in puzzle step, e.g. `coins`, `teleporter` or `vault`
+    ///
+    /// See `examples/challenge.walk` for a walkthrough of the whole game.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut actions = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+
+            let action = if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else if let Some(directive) = line.strip_prefix('@') {
+                let (name, arg) = directive.split_once(' ').unwrap_or((directive, ""));
+                let arg = arg.trim().to_string();
+
+                match name {
+                    "room" => Action::ExpectRoom(arg),
+                    "expect" => Action::ExpectOutput(arg),
+                    "step" => Action::Step(arg),
+                    _ => return Err(format!("line {}: unknown directive @{}", i + 1, name)),
+                }
+            } else {
+                Action::Command(line.into())
+            };
+
+            actions.push((i + 1, action));
+        }
+
+        let mut walkthrough = Self { actions, steps: HashMap::new() };
+        walkthrough.register(Box::new(Coins));
+        walkthrough.register(Box::new(Teleporter));
+        walkthrough.register(Box::new(Vault));
+
+        Ok(walkthrough)
+    }
+
+    pub fn register(&mut self, step: Box<dyn PuzzleStep>) {
+        self.steps.insert(step.name(), step);
+    }
+
+    /// Plays the walkthrough from the current state, returning the codes found.
+    pub fn run(&self, session: &mut GameSession) -> Result<Vec<String>, String> {
+        session.set_step_limit(Some(STEP_LIMIT));
+
+        let mut codes = Vec::new();
+        let mut last_output = session.run();
+        let mut room = parse_room(&last_output).map(|r| r.name);
+        report_codes(&last_output, &mut codes);
+
+        for (line, action) in &self.actions {
+            let fail = |msg: String| format!("line {}: {}", line, msg);
+
+            match action {
+                Action::Command(command) => {
+                    check_state(session).map_err(fail)?;
+                    last_output = session.send(command);
+                }
+                Action::Step(name) => {
+                    let step = self.steps.get(name.as_str())
+                        .ok_or_else(|| fail(format!("unknown puzzle step '{}'", name)))?;
+
+                    check_state(session).map_err(fail)?;
+                    last_output = step.run(session).map_err(fail)?;
+                    println!("{} {}", "Solved".green(), name);
+                }
+                Action::ExpectRoom(expected) => {
+                    if room.as_deref() != Some(expected.as_str()) {
+                        let actual = room.as_deref().unwrap_or("no room");
+                        return Err(fail(format!("expected to be in '{}', but was in '{}'", expected, actual)));
+                    }
+                    continue;
+                }
+                Action::ExpectOutput(expected) => {
+                    if !last_output.contains(expected.as_str()) {
+                        return Err(fail(format!("expected output containing '{}', got:\n{}", expected, last_output.trim())));
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(r) = parse_room(&last_output) {
+                room = Some(r.name);
+            }
+            report_codes(&last_output, &mut codes);
+        }
+
+        Ok(codes)
+    }
+}
+
+fn check_state(session: &GameSession) -> Result<(), String> {
+    match session.state() {
+        SessionState::WaitingForInput => Ok(()),
+        SessionState::Halted => Err("the game halted".into()),
+        SessionState::Faulted(fault) => Err(format!("the VM faulted\n{}", fault)),
+        SessionState::StepLimitReached => Err(format!("no prompt after {} instructions", STEP_LIMIT)),
+    }
+}
+
+fn report_codes(output: &str, codes: &mut Vec<String>) {
+    for code in find_codes(output) {
+        if !codes.contains(&code) {
+            println!("{} {}", "Code:".yellow().bold(), code);
+            codes.push(code);
+        }
+    }
+}
+
+/// Finds words that look like challenge codes: twelve letters and digits in mixed
+/// case, which rules out capitalized and shouted English words.
+pub fn find_codes(output: &str) -> Vec<String> {
+    output.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| w.len() == CODE_LEN)
+        .filter(|w| w.chars().any(|c| c.is_ascii_lowercase()))
+        .filter(|w| w.chars().filter(|c| c.is_ascii_uppercase() || c.is_ascii_digit()).count() >= 2)
+        .map(Into::into)
+        .collect()
+}
+
+fn send_all(session: &mut GameSession, commands: &[String]) -> Result<String, String> {
+    let mut output = String::new();
+
+    for command in commands {
+        check_state(session)?;
+        output.push_str(&session.send(command));
+    }
+
+    Ok(output)
+}
+
+/// Orders the five coins so that `_ + _ * _^2 + _^3 - _ = 399`.
+pub struct Coins;
+
+impl Coins {
+    fn value(description: &str) -> Option<u32> {
+        const WORDS: [(&str, u32); 7] = [
+            ("two dots", 2), ("three dots", 3), ("five dots", 5), ("seven dots", 7), ("nine dots", 9),
+            ("triangle", 3), ("pentagon", 5),
+        ];
+
+        WORDS.iter().find(|(word, _)| description.contains(word)).map(|&(_, val)| val)
+    }
+
+    /// Indices of `values` in the order that solves the equation.
+    fn order(values: &[u32]) -> Option<Vec<usize>> {
+        permutations(values.len()).into_iter().find(|p| {
+            let v = p.iter().map(|&i| values[i]).collect::<Vec<_>>();
+            v[0] + v[1] * v[2].pow(2) + v[3].pow(3) == 399 + v[4]
+        })
+    }
+}
+
+impl PuzzleStep for Coins {
+    fn name(&self) -> &'static str { "coins" }
+
+    fn run(&self, session: &mut GameSession) -> Result<String, String> {
+        let inventory = session.send("inv");
+        let coins = backend::parse_inventory(&inventory)
+            .ok_or("could not read the inventory")?
+            .into_iter()
+            .filter(|item| item.ends_with("coin"))
+            .collect::<Vec<_>>();
+
+        if coins.len() != 5 {
+            return Err(format!("expected 5 coins in the inventory, found {}", coins.len()));
+        }
+
+        let mut values = Vec::new();
+        for coin in &coins {
+            let description = parse_look(&session.send(&format!("look {}", coin)));
+            values.push(Coins::value(&description).ok_or_else(|| format!("could not tell the value of the {}", coin))?);
+        }
+
+        let order = Coins::order(&values).ok_or("no ordering of the coins solves the equation")?;
+
+        let commands = order.iter().map(|&i| format!("use {}", coins[i])).collect::<Vec<_>>();
+        send_all(session, &commands)
+    }
+}
+
+fn permutations(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![vec![]];
+    }
+
+    let mut out = Vec::new();
+    for p in permutations(n - 1) {
+        for i in 0..=p.len() {
+            let mut q = p.clone();
+            q.insert(i, n - 1);
+            out.push(q);
+        }
+    }
+    out
+}
+
+/// Skips the teleporter's confirmation routine and sets the eighth register.
+pub struct Teleporter;
+
+impl Teleporter {
+    pub const ENERGY_LEVEL: u16 = 25734;
+
+    /// Finds `call <check>; eq r1 r0 6` and the `set r0 4` leading up to it.
+    fn patch(memory: &mut Memory) -> Result<(), String> {
+        const CHECK: [u16; 4] = [4, 32769, 32768, 6];
+        const SEARCH_BACK: usize = 8;
+
+        let call = (0..memory.len() - 6)
+            .find(|&addr| memory[addr] == 17 && memory.read(addr + 2..addr + 6).eq(CHECK))
+            .ok_or("could not find the confirmation check")?;
+
+        let set = (call.saturating_sub(SEARCH_BACK)..call)
+            .find(|&addr| memory.read(addr..addr + 3).eq([1, 32768, 4]))
+            .ok_or("could not find the confirmation input")?;
+
+        memory.set(set + 2, 6);
+        memory.write(call, &[21, 21]);
+        Ok(())
+    }
+}
+
+impl PuzzleStep for Teleporter {
+    fn name(&self) -> &'static str { "teleporter" }
+
+    fn run(&self, session: &mut GameSession) -> Result<String, String> {
+        let vm = session.vm_mut();
+        Teleporter::patch(vm.memory_mut())?;
+        vm.registers_mut()[7] = Teleporter::ENERGY_LEVEL;
+
+        send_all(session, &["use teleporter".to_string()])
+    }
+}
+
+/// Walks the orb across the vault antechamber grid so that it weighs 30 at the door.
+pub struct Vault;
+
+impl Vault {
+    const GRID: [[&'static str; 4]; 4] = [
+        ["*", "8", "-", "1"],
+        ["4", "*", "11", "*"],
+        ["+", "4", "-", "18"],
+        ["22", "-", "9", "*"],
+    ];
+    const START: (usize, usize) = (3, 0);
+    const GOAL: (usize, usize) = (0, 3);
+    const TARGET: i32 = 30;
+    const MAX_WEIGHT: i32 = 1000;
+
+    fn solve() -> Option<Vec<&'static str>> {
+        const MOVES: [(&str, isize, isize); 4] = [("north", -1, 0), ("south", 1, 0), ("east", 0, 1), ("west", 0, -1)];
+
+        let start = (Vault::START, 22, None);
+        let mut queue = VecDeque::from([(start, Vec::new())]);
+        let mut seen = HashSet::from([start]);
+
+        while let Some((((row, col), weight, op), path)) = queue.pop_front() {
+            for &(direction, dr, dc) in &MOVES {
+                let (r, c) = (row as isize + dr, col as isize + dc);
+                if !(0..4).contains(&r) || !(0..4).contains(&c) || (r as usize, c as usize) == Vault::START {
+                    continue;
+                }
+
+                let pos = (r as usize, c as usize);
+                let cell = Vault::GRID[pos.0][pos.1];
+                let (weight, op) = match (cell.parse::<i32>(), op) {
+                    (Ok(val), Some("+")) => (weight + val, None),
+                    (Ok(val), Some("-")) => (weight - val, None),
+                    (Ok(val), Some(_)) => (weight * val, None),
+                    (Ok(_), None) => continue,
+                    (Err(_), _) => (weight, Some(cell)),
+                };
+
+                if weight <= 0 || weight > Vault::MAX_WEIGHT {
+                    continue;
+                }
+
+                let mut path = path.clone();
+                path.push(direction);
+
+                if pos == Vault::GOAL {
+                    if weight == Vault::TARGET {
+                        return Some(path);
+                    }
+                    continue;
+                }
+
+                let state = (pos, weight, op);
+                if seen.insert(state) {
+                    queue.push_back((state, path));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl PuzzleStep for Vault {
+    fn name(&self) -> &'static str { "vault" }
+
+    fn run(&self, session: &mut GameSession) -> Result<String, String> {
+        let path = Vault::solve().ok_or("no path through the vault grid")?;
+        let commands = path.iter().map(|d| format!("go {}", d)).collect::<Vec<_>>();
+        send_all(session, &commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_walkthrough() {
+        let walkthrough = Walkthrough::parse(include_str!("../../examples/challenge.walk")).unwrap();
+        let steps = walkthrough.actions.iter()
+            .filter_map(|(_, action)| match action {
+                Action::Step(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(steps, ["coins", "teleporter", "vault"]);
+        assert!(steps.iter().all(|step| walkthrough.steps.contains_key(step)));
+    }
+
+    #[test]
+    fn coin_order() {
+        let coins = ["red coin", "corroded coin", "shiny coin", "concave coin", "blue coin"];
+        let descriptions = ["two dots", "a triangle", "a pentagon", "seven dots", "nine dots"];
+
+        let values = descriptions.iter().map(|d| Coins::value(&format!("This coin is made of metal. It has {} on one side.", d)).unwrap()).collect::<Vec<_>>();
+        assert_eq!(values, [2, 3, 5, 7, 9]);
+
+        let order = Coins::order(&values).unwrap().into_iter().map(|i| coins[i]).collect::<Vec<_>>();
+        assert_eq!(order, ["blue coin", "red coin", "shiny coin", "concave coin", "corroded coin"]);
+
+        assert_eq!(Coins::order(&[1, 1, 1, 1, 1]), None);
+        assert_eq!(Coins::value("It's a coin."), None);
+    }
+
+    #[test]
+    fn vault_path() {
+        assert_eq!(Vault::solve().unwrap(), ["north", "east", "east", "north", "west", "south", "east", "east", "west", "north", "north", "east"]);
+    }
+
+    #[test]
+    fn codes_in_output() {
+        let output = "You find yourself writing \"wmFoYTzdvUUK\" on the tablet.\nPerhaps it's some kind of code?\n";
+        assert_eq!(find_codes(output), ["wmFoYTzdvUUK"]);
+
+        // Twelve-letter words that aren't codes.
+        assert!(find_codes("Interestings INTERESTINGS interestings understood").is_empty());
+        assert_eq!(find_codes("abcDEFghiJKL, QQQQQQQQQQQq and a1b2c3d4e5f6"), ["abcDEFghiJKL", "QQQQQQQQQQQq", "a1b2c3d4e5f6"]);
+    }
+}
@@ -0,0 +1,110 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use backend::VmConfig;
+use frontend::TerminalVM;
+
+/// `set r0 1`, then `add r1 r1 r0` and `jmp` back to it forever.
+const LOOP: [u16; 9] = [1, 32768, 1, 9, 32769, 32769, 32768, 6, 3];
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) -> String {
+        self.send_bytes(data.as_bytes())
+    }
+
+    fn send_bytes(&mut self, data: &[u8]) -> String {
+        self.write(data);
+        self.reply()
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        let checksum = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        self.stream.write_all(b"$").unwrap();
+        self.stream.write_all(data).unwrap();
+        write!(self.stream, "#{:02x}", checksum).unwrap();
+        assert_eq!(self.byte(), b'+');
+    }
+
+    fn interrupt(&mut self) -> String {
+        self.stream.write_all(&[0x03]).unwrap();
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                b => data.push(b),
+            }
+        }
+
+        let checksum = [self.byte(), self.byte()];
+        assert_eq!(u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(), data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)));
+        String::from_utf8(data).unwrap()
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut buf = [0];
+        self.stream.read_exact(&mut buf).unwrap();
+        buf[0]
+    }
+}
+
+#[test]
+fn scripted_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let client = thread::spawn(move || {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut client = Client { stream };
+
+        assert_eq!(client.send("?"), "S05");
+        assert_eq!(client.send("g"), "0000".repeat(10));
+        assert_eq!(client.send(""), "");
+        assert_eq!(client.send_bytes(b"\xff"), "");
+        assert_eq!(client.send_bytes(b"\xc3\xa9"), "");
+
+        assert_eq!(client.send("s"), "S05");
+        assert_eq!(client.send("p0"), "0100");
+        assert_eq!(client.send("p8"), "0300");
+
+        assert_eq!(client.send("m0,3"), "010000800100");
+        assert_eq!(client.send("M100,2:3412cdab"), "OK");
+        assert_eq!(client.send("m100,2"), "3412cdab");
+        assert_eq!(client.send("m7ffe,ffffffffffffffff"), "00000000");
+        assert_eq!(client.send("M1,ffffffffffffffff:0000"), "E01");
+
+        // A rejected packet must leave every register alone.
+        assert_eq!(client.send(&format!("G{}0080", "0200".repeat(9))), "E01");
+        assert_eq!(client.send(&format!("G{}00800000", "0200".repeat(8))), "E01");
+        assert_eq!(client.send("P9=1100"), "E01");
+        assert_eq!(client.send("P0=0080"), "E01");
+        assert_eq!(client.send("p0"), "0100");
+
+        assert_eq!(client.send("Z0,7"), "OK");
+        assert_eq!(client.send("c"), "S05");
+        assert_eq!(client.send("p8"), "0700");
+        assert_eq!(client.send("z0,7"), "OK");
+
+        // Interrupting the endless loop gets exactly one stop reply.
+        client.write(b"c");
+        assert_eq!(client.interrupt(), "S02");
+        assert_eq!(client.send("?"), "S05");
+        assert_eq!(client.send("D"), "OK");
+    });
+
+    let mut term = TerminalVM::new();
+    term.set_config(VmConfig { stack_limit: Some(0x10), ..VmConfig::strict() });
+    term.load_binary(&LOOP);
+    frontend::gdb::serve(&mut term, listener).unwrap();
+    client.join().unwrap();
+}